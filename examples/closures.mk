@@ -0,0 +1,24 @@
+let adder = fn(x) { fn(y) { x + y } };
+let add_two = adder(2);
+println(add_two(3));
+
+let map = fn(xs, f) {
+  let iter = fn(rest, out) {
+    if (len(rest) == 0) {
+      return out;
+    }
+    iter(drop(rest, 1), push(out, f(rest[0])))
+  };
+  iter(xs, [])
+};
+
+let reduce = fn(xs, initial, f) {
+  if (len(xs) == 0) {
+    return initial;
+  }
+  reduce(drop(xs, 1), f(initial, xs[0]), f)
+};
+
+let numbers = [1, 2, 3, 4, 5];
+println(map(numbers, fn(x) { x * x }));
+reduce(numbers, 0, fn(sum, x) { sum + x })
@@ -0,0 +1,16 @@
+let people = [
+  {"name": "Ada", "born": 1815},
+  {"name": "Grace", "born": 1906},
+];
+let names = fn(people) {
+  if (len(people) == 0) {
+    return [];
+  }
+  let rest = names(drop(people, 1));
+  insert(rest, 0, people[0]["name"])
+};
+println(names(people));
+println(len(people), "people");
+println(people[1]["name"], "was born in", people[1]["born"]);
+println(any(people, fn(person) { person["born"] > 1900 }));
+println(pop([1, 2, 3]));
@@ -0,0 +1,5 @@
+// A runtime error stops the program, and is reported with where it happened.
+let divide = fn(a, b) { a / b };
+println(divide(10, 2));
+let unused = 1;
+divide(1, 0)
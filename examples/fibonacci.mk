@@ -0,0 +1,16 @@
+let fib = fn(n) {
+  if (n < 2) {
+    return n;
+  }
+  fib(n - 1) + fib(n - 2)
+};
+
+let table = fn(n, results) {
+  if (n < 0) {
+    return results;
+  }
+  table(n - 1, push(results, fib(n)))
+};
+
+println(table(10, []));
+fib(20)
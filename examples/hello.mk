@@ -0,0 +1,2 @@
+let greet = fn(name) { "Hello, " + name + "!" };
+println(greet("world"));
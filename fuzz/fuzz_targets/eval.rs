@@ -0,0 +1,35 @@
+//! Prints a random program, checks that it parses back into the same program, and runs it.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use monkey::{
+    ast::Program,
+    eval::Environment,
+    lint,
+    parse::parse_program,
+    report::Source,
+    sync::{Rc, RefCell},
+};
+
+fuzz_target!(|program: Program| {
+    let text = program.to_string();
+    let source = Source { name: "fuzz", text: &text };
+    let program = parse_program(&text)
+        .unwrap_or_else(|errors| panic!("generated program doesn't parse: {errors:?}\n{text}"));
+    assert_eq!(program.to_string(), text, "program changed when reparsed");
+    for warning in lint::check(&program) {
+        warning.write_report(source, program.strict, std::io::sink());
+    }
+
+    let mut env = Environment {
+        // Deep enough to find problems with recursion, but not to overflow the fuzzer's stack.
+        max_depth: 32,
+        steps_remaining: Some(10_000),
+        output: Some(Rc::new(RefCell::new(std::io::sink()))),
+        ..Default::default()
+    };
+    if let Err(error) = env.eval(program) {
+        error.write_report(source, std::io::sink());
+    }
+});
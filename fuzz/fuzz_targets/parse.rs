@@ -0,0 +1,29 @@
+//! Tokenizes, parses, and lints arbitrary text, and reports any errors.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use monkey::{
+    lint,
+    parse::{parse_program, tokenize},
+    report::Source,
+};
+
+fuzz_target!(|text: &str| {
+    let source = Source { name: "fuzz", text };
+    if let Err(error) = tokenize(text) {
+        error.write_report(source, std::io::sink());
+    }
+    match parse_program(text) {
+        Ok(program) => {
+            for warning in lint::check(&program) {
+                warning.write_report(source, program.strict, std::io::sink());
+            }
+        }
+        Err(errors) => {
+            for error in errors {
+                error.write_report(source, std::io::sink());
+            }
+        }
+    }
+});
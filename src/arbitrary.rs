@@ -0,0 +1,201 @@
+//! Random programs for fuzzing, generated from [`arbitrary`] input.
+//!
+//! Generated programs always print as source that parses back into the same program, so a fuzz
+//! target can run them through the whole pipeline from text. They're kept from anything that
+//! would touch the outside world, but not from mistakes like calling a non-function or indexing
+//! out of bounds, since reporting those is part of what's being tested.
+//!
+//! The fuzz targets that use this are in `fuzz/`, and run with `cargo fuzz run eval`.
+
+use crate::ast::{
+    Block, Expression, Identifier, InfixOperator, Prefix, PrefixOperator, Program, Span, Statement,
+};
+use arbitrary::{Arbitrary, Result, Unstructured};
+
+/// Names that programs bind and refer to. There are only a few so that references usually
+/// resolve, and so that functions sometimes end up calling themselves.
+const NAMES: &[&str] = &["a", "b", "c", "f", "g", "xs"];
+
+/// Intrinsics that only work with their arguments, and can't allocate much more than they're
+/// given.
+const INTRINSICS: &[&str] = &[
+    "println", "push", "pop", "insert", "remove", "take", "drop", "chunk", "windows", "find",
+    "any", "all", "error", "is_error", "clone", "len", "slice",
+];
+
+const INFIX_OPERATORS: &[InfixOperator] = &[
+    InfixOperator::Add,
+    InfixOperator::Sub,
+    InfixOperator::Mul,
+    InfixOperator::Div,
+    InfixOperator::Eq,
+    InfixOperator::Neq,
+    InfixOperator::LT,
+    InfixOperator::GT,
+];
+
+/// How deeply expressions and blocks can nest.
+const MAX_NESTING: usize = 6;
+
+impl<'a> Arbitrary<'a> for Program<'a> {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let statements = Generator::default().statements(u, 8)?;
+        Ok(Program::new(statements, Vec::new()))
+    }
+}
+
+#[derive(Default)]
+struct Generator {
+    nesting: usize,
+}
+
+impl Generator {
+    /// Up to `max` statements. Blocks print their statements on one line, so all but the last end
+    /// with `;`.
+    fn statements(&mut self, u: &mut Unstructured, max: usize) -> Result<Vec<Statement<'static>>> {
+        let count = u.int_in_range(0..=max)?;
+        (0..count)
+            .map(|i| self.statement(u, i + 1 == count))
+            .collect()
+    }
+
+    fn statement(&mut self, u: &mut Unstructured, last: bool) -> Result<Statement<'static>> {
+        Ok(match u.int_in_range(0..=2)? {
+            0 => Statement::Let {
+                let_span: Span::default(),
+                name: identifier(u, NAMES)?,
+                value: self.expression(u, true)?,
+            },
+            1 => Statement::Return {
+                return_span: Span::default(),
+                value: self.expression(u, true)?,
+            },
+            _ => Statement::Expression {
+                value: self.expression(u, true)?,
+                semi: !last || u.arbitrary()?,
+            },
+        })
+    }
+
+    fn block(&mut self, u: &mut Unstructured) -> Result<Block<'static>> {
+        Ok(Block {
+            open_span: Span::default(),
+            statements: self.statements(u, 3)?,
+            close_span: Span::default(),
+        })
+    }
+
+    /// A prefix operator applies to everything after it, so an expression that's followed by an
+    /// operator or brackets can't be a prefix expression, which `prefix` rules out.
+    fn expression(&mut self, u: &mut Unstructured, prefix: bool) -> Result<Expression<'static>> {
+        if self.nesting >= MAX_NESTING || u.is_empty() || u.ratio(1, 3)? {
+            return literal(u);
+        }
+        self.nesting += 1;
+        let expression = self.compound(u, prefix);
+        self.nesting -= 1;
+        expression
+    }
+
+    fn compound(&mut self, u: &mut Unstructured, prefix: bool) -> Result<Expression<'static>> {
+        Ok(match u.int_in_range(0..=7)? {
+            0 if prefix => Expression::Prefix {
+                prefix: Prefix {
+                    span: Span::default(),
+                    operator: u
+                        .choose(&[PrefixOperator::Neg, PrefixOperator::Not])?
+                        .clone(),
+                },
+                right: Box::new(self.expression(u, true)?),
+            },
+            0 | 1 => Expression::Infix {
+                left: Box::new(self.expression(u, false)?),
+                operator: *u.choose(INFIX_OPERATORS)?,
+                right: Box::new(self.expression(u, true)?),
+            },
+            2 => Expression::If {
+                if_span: Span::default(),
+                condition: Box::new(self.expression(u, true)?),
+                consequence: self.block(u)?,
+                alternative: if u.arbitrary()? {
+                    Some(self.block(u)?)
+                } else {
+                    None
+                },
+            },
+            3 => Expression::Function {
+                fn_span: Span::default(),
+                parameters: (0..u.int_in_range(0..=2)?)
+                    .map(|_| identifier(u, NAMES))
+                    .collect::<Result<_>>()?,
+                body: self.block(u)?,
+            },
+            4 => Expression::Call {
+                function: Box::new(if u.arbitrary()? {
+                    Expression::Identifier(identifier(u, INTRINSICS)?)
+                } else {
+                    self.expression(u, false)?
+                }),
+                arguments: self.expressions(u)?,
+                close_span: Span::default(),
+            },
+            5 => Expression::Array {
+                open_span: Span::default(),
+                elements: self.expressions(u)?,
+                close_span: Span::default(),
+            },
+            6 => Expression::Index {
+                collection: Box::new(self.expression(u, false)?),
+                index: Box::new(self.expression(u, true)?),
+                close_span: Span::default(),
+            },
+            _ => Expression::Map {
+                open_span: Span::default(),
+                elements: (0..u.int_in_range(0..=3)?)
+                    .map(|_| Ok((self.expression(u, true)?, self.expression(u, true)?)))
+                    .collect::<Result<_>>()?,
+                close_span: Span::default(),
+            },
+        })
+    }
+
+    fn expressions(&mut self, u: &mut Unstructured) -> Result<Vec<Expression<'static>>> {
+        (0..u.int_in_range(0..=3)?)
+            .map(|_| self.expression(u, true))
+            .collect()
+    }
+}
+
+fn literal(u: &mut Unstructured) -> Result<Expression<'static>> {
+    Ok(match u.int_in_range(0..=4)? {
+        0 => Expression::Identifier(identifier(u, NAMES)?),
+        1 => Expression::Integer {
+            span: Span::default(),
+            // Mostly small numbers, so that they're useful as indices and lengths.
+            value: if u.ratio(1, 8)? {
+                u.int_in_range(0..=i64::MAX)?
+            } else {
+                u.int_in_range(0..=10)?
+            },
+        },
+        2 => Expression::Boolean {
+            span: Span::default(),
+            value: u.arbitrary()?,
+        },
+        3 => Expression::Null(Span::default()),
+        _ => Expression::String {
+            span: Span::default(),
+            // Any characters, since strings print escaped so that they parse back the same.
+            value: (0..u.int_in_range(0..=8)?)
+                .map(|_| u.arbitrary::<char>())
+                .collect::<Result<_>>()?,
+        },
+    })
+}
+
+fn identifier(u: &mut Unstructured, names: &[&'static str]) -> Result<Identifier<'static>> {
+    Ok(Identifier {
+        name: u.choose(names)?,
+        span: Span::default(),
+    })
+}
@@ -1,10 +1,12 @@
+//! The syntax tree that parsing produces, and that programs print back to source from.
+
 use std::{
     fmt::{Debug, Display},
     hash::Hash,
 };
 use strum::Display;
 
-#[derive(Default, Clone, Copy)]
+#[derive(Default, Clone, Copy, PartialEq, Eq)]
 pub struct Span {
     pub start: usize,
     pub end: usize,
@@ -19,22 +21,6 @@ impl Span {
     }
 }
 
-impl ariadne::Span for Span {
-    type SourceId = &'static str;
-
-    fn source(&self) -> &Self::SourceId {
-        &"input"
-    }
-
-    fn start(&self) -> usize {
-        self.start
-    }
-
-    fn end(&self) -> usize {
-        self.end
-    }
-}
-
 impl std::fmt::Debug for Span {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}..{}", self.start, self.end)
@@ -125,6 +111,8 @@ pub enum Statement<'a> {
         value: Expression<'a>,
         semi: bool,
     },
+    /// A statement that failed to parse, kept so that parsing can continue after it.
+    Error(crate::parse::Error),
 }
 
 impl Spanned for Statement<'_> {
@@ -135,6 +123,7 @@ impl Spanned for Statement<'_> {
             } => let_span.join(value.span()),
             Self::Return { return_span, value } => return_span.join(value.span()),
             Self::Expression { value, .. } => value.span(),
+            Self::Error(error) => error.span,
         }
     }
 }
@@ -148,6 +137,7 @@ impl DisplayIndented for Statement<'_> {
                 value.fmt_indented(f, indent)?;
                 if *semi { write!(f, ";") } else { Ok(()) }
             }
+            Self::Error(_) => write!(f, "<error>"),
         }
     }
 }
@@ -160,7 +150,7 @@ impl Display for Statement<'_> {
 
 impl Node for Statement<'_> {}
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct Block<'a> {
     pub open_span: Span,
     pub statements: Vec<Statement<'a>>,
@@ -194,7 +184,7 @@ impl Display for Block<'_> {
 
 impl Node for Block<'_> {}
 
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub enum Expression<'a> {
     Identifier(Identifier<'a>),
     Integer {
@@ -250,155 +240,429 @@ pub enum Expression<'a> {
         elements: Vec<(Self, Self)>,
         close_span: Span,
     },
+    /// An expression inside brackets that failed to parse, kept so that parsing can continue
+    /// after it.
+    Error(crate::parse::Error),
 }
 
 impl Spanned for Expression<'_> {
     fn span(&self) -> Span {
-        match self {
-            Self::Identifier(ident) => ident.span(),
-            Self::Integer { span, .. } => *span,
-            Self::Prefix {
-                prefix: operator,
-                right,
-            } => operator.span.join(right.span()),
-            Self::Infix { left, right, .. } => left.span().join(right.span()),
-            Self::Boolean { span, .. } => *span,
-            Self::If {
-                if_span,
-                consequence,
-                alternative,
-                ..
-            } => if_span.join(alternative.as_ref().unwrap_or(consequence).span()),
-            Self::Function { fn_span, body, .. } => fn_span.join(body.span()),
-            Self::Call {
-                function,
-                close_span,
-                ..
-            } => function.span().join(*close_span),
-            Self::Null(span) => *span,
-            Self::String { span, .. } => *span,
-            Self::Array {
-                open_span,
-                close_span,
-                ..
-            } => open_span.join(*close_span),
-            Self::Index {
-                collection,
-                close_span,
-                ..
-            } => collection.span().join(*close_span),
-            Self::Map {
-                open_span,
-                close_span,
-                ..
-            } => open_span.join(*close_span),
+        Span {
+            start: self.start(),
+            end: self.end(),
         }
     }
 }
 
-impl DisplayIndented for Expression<'_> {
-    fn fmt_indented(&self, f: &mut std::fmt::Formatter<'_>, indent: usize) -> std::fmt::Result {
-        match self {
-            Self::Identifier(ident) => Display::fmt(ident, f),
-            Self::Integer { value, .. } => Display::fmt(value, f),
-            Self::Prefix { prefix, right } => {
-                Display::fmt(prefix, f)?;
-                right.fmt_indented(f, indent)
+impl<'a> Expression<'a> {
+    /// Where the expression starts. Chains like `1 + 2 + ... + n` can nest arbitrarily deeply, so
+    /// this walks down the leftmost path through the expression instead of recursing.
+    fn start(&self) -> usize {
+        let mut expression = self;
+        loop {
+            expression = match expression {
+                Self::Infix { left, .. } => left,
+                Self::Call { function, .. } => function,
+                Self::Index { collection, .. } => collection,
+                Self::Identifier(ident) => return ident.span.start,
+                Self::Prefix { prefix, .. } => return prefix.span.start,
+                Self::If { if_span, .. } => return if_span.start,
+                Self::Function { fn_span, .. } => return fn_span.start,
+                Self::Array { open_span, .. } | Self::Map { open_span, .. } => {
+                    return open_span.start;
+                }
+                Self::Integer { span, .. }
+                | Self::Boolean { span, .. }
+                | Self::String { span, .. }
+                | Self::Null(span) => return span.start,
+                Self::Error(error) => return error.span.start,
+            };
+        }
+    }
+
+    /// Where the expression ends, found by walking down its rightmost path like [`start`].
+    ///
+    /// [`start`]: Self::start
+    fn end(&self) -> usize {
+        let mut expression = self;
+        loop {
+            expression = match expression {
+                Self::Prefix { right, .. } | Self::Infix { right, .. } => right,
+                Self::Identifier(ident) => return ident.span.end,
+                Self::If {
+                    consequence,
+                    alternative,
+                    ..
+                } => return alternative.as_ref().unwrap_or(consequence).close_span.end,
+                Self::Function { body, .. } => return body.close_span.end,
+                Self::Call { close_span, .. }
+                | Self::Index { close_span, .. }
+                | Self::Array { close_span, .. }
+                | Self::Map { close_span, .. } => return close_span.end,
+                Self::Integer { span, .. }
+                | Self::Boolean { span, .. }
+                | Self::String { span, .. }
+                | Self::Null(span) => return span.end,
+                Self::Error(error) => return error.span.end,
+            };
+        }
+    }
+
+    /// Moves the expression out, leaving a placeholder behind. Expressions can't be destructured
+    /// by value, since they implement [`Drop`].
+    pub(crate) fn take(&mut self) -> Self {
+        std::mem::replace(self, Self::Null(Span::default()))
+    }
+
+    /// Moves the expressions directly inside this one onto `stack`, including those in the
+    /// statements of its blocks, leaving placeholders behind.
+    fn take_children(&mut self, stack: &mut Vec<Self>) {
+        fn take_block<'a>(block: &mut Block<'a>, stack: &mut Vec<Expression<'a>>) {
+            for statement in block.statements.drain(..) {
+                match statement {
+                    Statement::Let { value, .. }
+                    | Statement::Return { value, .. }
+                    | Statement::Expression { value, .. } => stack.push(value),
+                    Statement::Error(_) => {}
+                }
             }
-            Self::Infix {
-                left,
-                operator,
-                right,
-            } => {
-                write!(f, "(")?;
-                left.fmt_indented(f, indent)?;
-                write!(f, " {operator} ")?;
-                right.fmt_indented(f, indent)?;
-                write!(f, ")")
+        }
+
+        match self {
+            Self::Prefix { right, .. } => stack.push(right.take()),
+            Self::Infix { left, right, .. } => {
+                stack.push(left.take());
+                stack.push(right.take());
             }
-            Self::Boolean { value, .. } => write!(f, "{value}"),
             Self::If {
                 condition,
                 consequence,
                 alternative,
                 ..
             } => {
-                write!(f, "if ")?;
-                condition.fmt_indented(f, indent)?;
-                write!(f, " ")?;
-                consequence.fmt_indented(f, indent)?;
-                if let Some(alternative) = alternative.as_ref() {
-                    write!(f, " else ")?;
-                    alternative.fmt_indented(f, indent)?;
-                }
-                Ok(())
-            }
-            Self::Function {
-                parameters, body, ..
-            } => {
-                write!(
-                    f,
-                    "fn({}",
-                    parameters
-                        .first()
-                        .map(ToString::to_string)
-                        .unwrap_or_default()
-                )?;
-                for parameter in parameters.iter().skip(1) {
-                    write!(f, ", {parameter}")?;
+                stack.push(condition.take());
+                take_block(consequence, stack);
+                if let Some(alternative) = alternative {
+                    take_block(alternative, stack);
                 }
-                write!(f, ") ")?;
-                body.fmt_indented(f, indent)
             }
+            Self::Function { body, .. } => take_block(body, stack),
             Self::Call {
                 function,
                 arguments,
                 ..
             } => {
-                function.fmt_indented(f, indent)?;
-                write!(f, "(")?;
-                if let Some(first) = arguments.first() {
-                    first.fmt_indented(f, indent)?;
-                }
-                for argument in arguments.iter().skip(1) {
-                    write!(f, ", ")?;
-                    argument.fmt_indented(f, indent)?;
-                }
-                write!(f, ")")
-            }
-            Self::Null(_) => write!(f, "null"),
-            Self::String { value, .. } => write!(f, "{value:?}"),
-            Self::Array { elements, .. } => {
-                write!(f, "[")?;
-                if let Some(first) = elements.first() {
-                    first.fmt_indented(f, indent)?;
-                }
-                for element in elements.iter().skip(1) {
-                    write!(f, ", ")?;
-                    element.fmt_indented(f, indent)?;
-                }
-                write!(f, "]")
+                stack.push(function.take());
+                stack.append(arguments);
             }
+            Self::Array { elements, .. } => stack.append(elements),
             Self::Index {
                 collection, index, ..
             } => {
-                collection.fmt_indented(f, indent)?;
-                write!(f, "[")?;
-                index.fmt_indented(f, indent)?;
-                write!(f, "]")
+                stack.push(collection.take());
+                stack.push(index.take());
             }
             Self::Map { elements, .. } => {
-                writeln!(f, "{{")?;
-                for (key, value) in elements.iter() {
-                    write_indent(f, indent + 1)?;
-                    key.fmt_indented(f, indent + 1)?;
-                    write!(f, ": ")?;
-                    value.fmt_indented(f, indent + 1)?;
-                    writeln!(f, ",")?;
+                for (key, value) in elements.drain(..) {
+                    stack.push(key);
+                    stack.push(value);
+                }
+            }
+            Self::Identifier(_)
+            | Self::Integer { .. }
+            | Self::Boolean { .. }
+            | Self::Null(_)
+            | Self::String { .. }
+            | Self::Error(_) => {}
+        }
+    }
+}
+
+impl Clone for Expression<'_> {
+    /// Clones the operands of operators, calls, and indexing from a stack, so that cloning a
+    /// deeply nested expression can't overflow the native stack.
+    fn clone(&self) -> Self {
+        enum Work<'s, 'a> {
+            Visit(&'s Expression<'a>),
+            /// Builds the clone of an expression from the clones of its operands, which are on top
+            /// of the stack of finished clones.
+            Build(&'s Expression<'a>),
+        }
+
+        let mut work = vec![Work::Visit(self)];
+        let mut finished = Vec::new();
+        while let Some(item) = work.pop() {
+            match item {
+                Work::Visit(expression) => {
+                    work.push(Work::Build(expression));
+                    // Pushed in reverse, so that they're finished in order.
+                    match expression {
+                        Self::Prefix { right, .. } => work.push(Work::Visit(right)),
+                        Self::Infix { left, right, .. } => {
+                            work.push(Work::Visit(right));
+                            work.push(Work::Visit(left));
+                        }
+                        Self::If { condition, .. } => work.push(Work::Visit(condition)),
+                        Self::Call { function, .. } => work.push(Work::Visit(function)),
+                        Self::Index {
+                            collection, index, ..
+                        } => {
+                            work.push(Work::Visit(index));
+                            work.push(Work::Visit(collection));
+                        }
+                        _ => {}
+                    }
+                }
+                Work::Build(expression) => {
+                    let mut operand = || Box::new(finished.pop().expect("operands are finished"));
+                    let clone = match expression {
+                        Self::Identifier(ident) => Self::Identifier(ident.clone()),
+                        &Self::Integer { span, value } => Self::Integer { span, value },
+                        Self::Prefix { prefix, .. } => Self::Prefix {
+                            prefix: prefix.clone(),
+                            right: operand(),
+                        },
+                        &Self::Infix { operator, .. } => {
+                            let right = operand();
+                            Self::Infix {
+                                left: operand(),
+                                operator,
+                                right,
+                            }
+                        }
+                        &Self::Boolean { span, value } => Self::Boolean { span, value },
+                        Self::If {
+                            if_span,
+                            consequence,
+                            alternative,
+                            ..
+                        } => Self::If {
+                            if_span: *if_span,
+                            condition: operand(),
+                            consequence: consequence.clone(),
+                            alternative: alternative.clone(),
+                        },
+                        Self::Function {
+                            fn_span,
+                            parameters,
+                            body,
+                        } => Self::Function {
+                            fn_span: *fn_span,
+                            parameters: parameters.clone(),
+                            body: body.clone(),
+                        },
+                        Self::Call {
+                            arguments,
+                            close_span,
+                            ..
+                        } => Self::Call {
+                            function: operand(),
+                            arguments: arguments.clone(),
+                            close_span: *close_span,
+                        },
+                        &Self::Null(span) => Self::Null(span),
+                        Self::Error(error) => Self::Error(error.clone()),
+                        Self::String { span, value } => Self::String {
+                            span: *span,
+                            value: value.clone(),
+                        },
+                        Self::Array {
+                            open_span,
+                            elements,
+                            close_span,
+                        } => Self::Array {
+                            open_span: *open_span,
+                            elements: elements.clone(),
+                            close_span: *close_span,
+                        },
+                        &Self::Index { close_span, .. } => {
+                            let index = operand();
+                            Self::Index {
+                                collection: operand(),
+                                index,
+                                close_span,
+                            }
+                        }
+                        Self::Map {
+                            open_span,
+                            elements,
+                            close_span,
+                        } => Self::Map {
+                            open_span: *open_span,
+                            elements: elements.clone(),
+                            close_span: *close_span,
+                        },
+                    };
+                    finished.push(clone);
+                }
+            }
+        }
+        finished.pop().expect("the expression was cloned")
+    }
+}
+
+impl Drop for Expression<'_> {
+    /// Drops nested expressions one at a time from a stack, so that dropping a deeply nested
+    /// expression can't overflow the native stack.
+    fn drop(&mut self) {
+        let mut stack = Vec::new();
+        self.take_children(&mut stack);
+        while let Some(mut expression) = stack.pop() {
+            expression.take_children(&mut stack);
+        }
+    }
+}
+
+impl DisplayIndented for Expression<'_> {
+    /// Writes nested expressions and the text between them from a stack, so that writing a deeply
+    /// nested expression can't overflow the native stack.
+    fn fmt_indented(&self, f: &mut std::fmt::Formatter<'_>, indent: usize) -> std::fmt::Result {
+        enum Work<'s, 'a> {
+            Expression(&'s Expression<'a>, usize),
+            Block(&'s Block<'a>, usize),
+            Operator(InfixOperator),
+            Text(&'static str),
+            Indent(usize),
+        }
+
+        /// Pushes `expressions` separated by commas, in reverse so that they're written in order.
+        fn push_list<'s, 'a>(
+            work: &mut Vec<Work<'s, 'a>>,
+            expressions: &'s [Expression<'a>],
+            indent: usize,
+        ) {
+            for (i, expression) in expressions.iter().enumerate().rev() {
+                work.push(Work::Expression(expression, indent));
+                if i > 0 {
+                    work.push(Work::Text(", "));
+                }
+            }
+        }
+
+        let mut work = vec![Work::Expression(self, indent)];
+        while let Some(item) = work.pop() {
+            let (expression, indent) = match item {
+                Work::Expression(expression, indent) => (expression, indent),
+                Work::Block(block, indent) => {
+                    block.fmt_indented(f, indent)?;
+                    continue;
+                }
+                Work::Operator(operator) => {
+                    write!(f, " {operator} ")?;
+                    continue;
+                }
+                Work::Text(text) => {
+                    f.write_str(text)?;
+                    continue;
+                }
+                Work::Indent(indent) => {
+                    write_indent(f, indent)?;
+                    continue;
+                }
+            };
+            // The parts after what's written now are pushed in reverse, so that they're written
+            // in order.
+            match expression {
+                Self::Identifier(ident) => Display::fmt(ident, f)?,
+                Self::Integer { value, .. } => Display::fmt(value, f)?,
+                Self::Prefix { prefix, right } => {
+                    Display::fmt(prefix, f)?;
+                    work.push(Work::Expression(right, indent));
+                }
+                Self::Infix {
+                    left,
+                    operator,
+                    right,
+                } => {
+                    write!(f, "(")?;
+                    work.extend([
+                        Work::Text(")"),
+                        Work::Expression(right, indent),
+                        Work::Operator(*operator),
+                        Work::Expression(left, indent),
+                    ]);
+                }
+                Self::Boolean { value, .. } => write!(f, "{value}")?,
+                Self::If {
+                    condition,
+                    consequence,
+                    alternative,
+                    ..
+                } => {
+                    if let Some(alternative) = alternative.as_ref() {
+                        work.extend([Work::Block(alternative, indent), Work::Text(" else ")]);
+                    }
+                    work.push(Work::Block(consequence, indent));
+                    // Infix expressions are already parenthesized.
+                    if matches!(**condition, Self::Infix { .. }) {
+                        write!(f, "if ")?;
+                        work.push(Work::Text(" "));
+                    } else {
+                        write!(f, "if (")?;
+                        work.push(Work::Text(") "));
+                    }
+                    work.push(Work::Expression(condition, indent));
+                }
+                Self::Function {
+                    parameters, body, ..
+                } => {
+                    write!(
+                        f,
+                        "fn({}",
+                        parameters
+                            .first()
+                            .map(ToString::to_string)
+                            .unwrap_or_default()
+                    )?;
+                    for parameter in parameters.iter().skip(1) {
+                        write!(f, ", {parameter}")?;
+                    }
+                    write!(f, ") ")?;
+                    work.push(Work::Block(body, indent));
+                }
+                Self::Call {
+                    function,
+                    arguments,
+                    ..
+                } => {
+                    work.push(Work::Text(")"));
+                    push_list(&mut work, arguments, indent);
+                    work.extend([Work::Text("("), Work::Expression(function, indent)]);
                 }
-                write!(f, "}}")
+                Self::Null(_) => write!(f, "null")?,
+                Self::String { value, .. } => write!(f, "{}", Quoted(value))?,
+                Self::Array { elements, .. } => {
+                    write!(f, "[")?;
+                    work.push(Work::Text("]"));
+                    push_list(&mut work, elements, indent);
+                }
+                Self::Index {
+                    collection, index, ..
+                } => {
+                    work.extend([
+                        Work::Text("]"),
+                        Work::Expression(index, indent),
+                        Work::Text("["),
+                        Work::Expression(collection, indent),
+                    ]);
+                }
+                Self::Map { elements, .. } => {
+                    writeln!(f, "{{")?;
+                    work.push(Work::Text("}"));
+                    for (key, value) in elements.iter().rev() {
+                        work.extend([
+                            Work::Text(",\n"),
+                            Work::Expression(value, indent + 1),
+                            Work::Text(": "),
+                            Work::Expression(key, indent + 1),
+                            Work::Indent(indent + 1),
+                        ]);
+                    }
+                }
+                Self::Error(_) => write!(f, "<error>")?,
             }
         }
+        Ok(())
     }
 }
 
@@ -408,6 +672,27 @@ impl Display for Expression<'_> {
     }
 }
 
+/// A string as a literal that parses back to it, escaped only in ways the parser understands.
+pub(crate) struct Quoted<'s>(pub &'s str);
+
+impl Display for Quoted<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "\"")?;
+        for c in self.0.chars() {
+            match c {
+                '"' => write!(f, "\\\"")?,
+                '\\' => write!(f, "\\\\")?,
+                '\n' => write!(f, "\\n")?,
+                '\r' => write!(f, "\\r")?,
+                '\t' => write!(f, "\\t")?,
+                c if c.is_control() => write!(f, "\\u{{{:x}}}", c as u32)?,
+                c => write!(f, "{c}")?,
+            }
+        }
+        write!(f, "\"")
+    }
+}
+
 impl Node for Expression<'_> {}
 
 #[derive(Debug, Clone)]
@@ -461,9 +746,75 @@ impl InfixOperator {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Program<'a> {
     pub statements: Vec<Statement<'a>>,
+    /// Every comment in the program, in source order.
+    pub comments: Vec<Comment<'a>>,
+    /// Whether the program opts into strict mode with a [`STRICT_PRAGMA`] comment before its first
+    /// statement.
+    pub strict: bool,
+}
+
+/// A comment that enables strict mode, in which warnings are errors and looking up a key that
+/// isn't in a map is an error rather than `null`.
+pub const STRICT_PRAGMA: &str = "// strict";
+
+/// A `//` comment. Comments don't affect evaluation, but are kept for tools that reproduce the
+/// source.
+#[derive(Debug, Clone, Copy)]
+pub struct Comment<'a> {
+    pub span: Span,
+    /// The comment's text, including the leading `//`.
+    pub text: &'a str,
+}
+
+impl Spanned for Comment<'_> {
+    fn span(&self) -> Span {
+        self.span
+    }
+}
+
+impl Display for Comment<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.text)
+    }
+}
+
+impl<'a> Program<'a> {
+    pub fn new(statements: Vec<Statement<'a>>, comments: Vec<Comment<'a>>) -> Self {
+        let first_statement = statements.first().map(|statement| statement.span().start);
+        let strict = comments
+            .iter()
+            .take_while(|comment| first_statement.is_none_or(|start| comment.span.start < start))
+            .any(|comment| comment.text.trim_end() == STRICT_PRAGMA);
+        Self {
+            statements,
+            comments,
+            strict,
+        }
+    }
+
+    /// The top-level statements, each preceded by the comments that start before it. Comments
+    /// inside a statement come before the whole statement. Comments after the last statement are
+    /// yielded last, with no statement.
+    fn interleaved(&self) -> impl Iterator<Item = (&[Comment<'a>], Option<&Statement<'a>>)> {
+        let mut comments = &self.comments[..];
+        self.statements
+            .iter()
+            .map(Some)
+            .chain([None])
+            .map(move |statement| {
+                let end = statement.map_or(usize::MAX, |statement| statement.span().end);
+                let count = comments
+                    .iter()
+                    .take_while(|comment| comment.span.start < end)
+                    .count();
+                let leading;
+                (leading, comments) = comments.split_at(count);
+                (leading, statement)
+            })
+    }
 }
 
 impl Spanned for Program<'_> {
@@ -478,9 +829,319 @@ impl Spanned for Program<'_> {
 
 impl Display for Program<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        for statement in &self.statements {
-            writeln!(f, "{statement}")?;
+        for (comments, statement) in self.interleaved() {
+            for comment in comments {
+                writeln!(f, "{comment}")?;
+            }
+            if let Some(statement) = statement {
+                writeln!(f, "{statement}")?;
+            }
         }
         Ok(())
     }
 }
+
+impl Program<'_> {
+    /// Renders the program as indented S-expressions that include every node's span.
+    pub fn to_sexp(&self) -> String {
+        let mut out = String::new();
+        tree::Tree::program(self).write_sexp(&mut out, 0);
+        out.push('\n');
+        out
+    }
+
+    /// Renders the program as a tree of node kinds and spans, drawn with box-drawing characters.
+    pub fn debug_tree(&self) -> String {
+        let mut out = String::new();
+        tree::Tree::program(self).write_tree(&mut out, "", "");
+        out
+    }
+}
+
+mod tree {
+    use super::*;
+    use std::fmt::Write;
+
+    /// A node reduced to a description of itself, its span, and its children, for printing.
+    pub struct Tree {
+        head: String,
+        span: Span,
+        children: Vec<Tree>,
+    }
+
+    impl Tree {
+        fn new(head: impl Display, span: Span, children: Vec<Tree>) -> Self {
+            Self {
+                head: head.to_string(),
+                span,
+                children,
+            }
+        }
+
+        fn leaf(head: impl Display, span: Span) -> Self {
+            Self::new(head, span, Vec::new())
+        }
+
+        pub fn program(program: &Program) -> Self {
+            let mut children = Vec::new();
+            for (comments, statement) in program.interleaved() {
+                children.extend(comments.iter().map(|comment| {
+                    Self::leaf(format_args!("comment {:?}", comment.text), comment.span)
+                }));
+                children.extend(statement.map(Self::statement));
+            }
+            Self::new("program", program.span(), children)
+        }
+
+        fn statement(statement: &Statement) -> Self {
+            let span = statement.span();
+            match statement {
+                Statement::Let { name, value, .. } => Self::new(
+                    "let",
+                    span,
+                    vec![Self::identifier(name), Self::expression(value)],
+                ),
+                Statement::Return { value, .. } => {
+                    Self::new("return", span, vec![Self::expression(value)])
+                }
+                Statement::Expression { value, semi } => Self::new(
+                    if *semi { "expression ;" } else { "expression" },
+                    span,
+                    vec![Self::expression(value)],
+                ),
+                Statement::Error(error) => {
+                    Self::leaf(format_args!("error {:?}", error.kind.to_string()), span)
+                }
+            }
+        }
+
+        fn identifier(identifier: &Identifier) -> Self {
+            Self::leaf(format_args!("ident {identifier}"), identifier.span)
+        }
+
+        fn block(block: &Block) -> Self {
+            Self::new(
+                "block",
+                block.span(),
+                block.statements.iter().map(Self::statement).collect(),
+            )
+        }
+
+        /// Builds the trees of nested expressions from a stack, so that a deeply nested expression
+        /// can't overflow the native stack.
+        fn expression(expression: &Expression) -> Self {
+            enum Work<'s, 'a> {
+                Visit(&'s Expression<'a>),
+                /// Builds the tree of an expression from the trees of its operands, which are on top
+                /// of the stack of finished trees.
+                Build(&'s Expression<'a>),
+            }
+
+            let mut work = vec![Work::Visit(expression)];
+            let mut finished = Vec::new();
+            while let Some(item) = work.pop() {
+                let expression = match item {
+                    Work::Visit(expression) => {
+                        work.push(Work::Build(expression));
+                        // Pushed in reverse, so that they're finished in order.
+                        match expression {
+                            Expression::Prefix { right, .. } => work.push(Work::Visit(right)),
+                            Expression::Infix { left, right, .. } => {
+                                work.push(Work::Visit(right));
+                                work.push(Work::Visit(left));
+                            }
+                            Expression::If { condition, .. } => work.push(Work::Visit(condition)),
+                            Expression::Call {
+                                function,
+                                arguments,
+                                ..
+                            } => {
+                                work.extend(arguments.iter().rev().map(Work::Visit));
+                                work.push(Work::Visit(function));
+                            }
+                            Expression::Array { elements, .. } => {
+                                work.extend(elements.iter().rev().map(Work::Visit));
+                            }
+                            Expression::Index {
+                                collection, index, ..
+                            } => {
+                                work.push(Work::Visit(index));
+                                work.push(Work::Visit(collection));
+                            }
+                            Expression::Map { elements, .. } => {
+                                for (key, value) in elements.iter().rev() {
+                                    work.push(Work::Visit(value));
+                                    work.push(Work::Visit(key));
+                                }
+                            }
+                            _ => {}
+                        }
+                        continue;
+                    }
+                    Work::Build(expression) => expression,
+                };
+                let mut operands = |count| {
+                    let at = finished.len() - count;
+                    finished.split_off(at)
+                };
+                let span = expression.span();
+                let tree = match expression {
+                    Expression::Identifier(ident) => Self::identifier(ident),
+                    Expression::Integer { value, .. } => {
+                        Self::leaf(format_args!("int {value}"), span)
+                    }
+                    Expression::Boolean { value, .. } => {
+                        Self::leaf(format_args!("bool {value}"), span)
+                    }
+                    Expression::Null(_) => Self::leaf("null", span),
+                    Expression::String { value, .. } => {
+                        Self::leaf(format_args!("string {value:?}"), span)
+                    }
+                    Expression::Prefix { prefix, .. } => {
+                        Self::new(format_args!("prefix {prefix}"), span, operands(1))
+                    }
+                    Expression::Infix { operator, .. } => {
+                        Self::new(format_args!("infix {operator}"), span, operands(2))
+                    }
+                    Expression::If {
+                        consequence,
+                        alternative,
+                        ..
+                    } => Self::new(
+                        "if",
+                        span,
+                        operands(1)
+                            .into_iter()
+                            .chain([Self::block(consequence)])
+                            .chain(alternative.as_ref().map(Self::block))
+                            .collect(),
+                    ),
+                    Expression::Function {
+                        parameters, body, ..
+                    } => Self::new(
+                        "fn",
+                        span,
+                        parameters
+                            .iter()
+                            .map(Self::identifier)
+                            .chain([Self::block(body)])
+                            .collect(),
+                    ),
+                    Expression::Call { arguments, .. } => {
+                        Self::new("call", span, operands(1 + arguments.len()))
+                    }
+                    Expression::Array { elements, .. } => {
+                        Self::new("array", span, operands(elements.len()))
+                    }
+                    Expression::Index { .. } => Self::new("index", span, operands(2)),
+                    Expression::Map { elements, .. } => {
+                        let mut operands = operands(2 * elements.len()).into_iter();
+                        let entries = elements
+                            .iter()
+                            .map(|(key, value)| {
+                                Self::new(
+                                    "entry",
+                                    key.span().join(value.span()),
+                                    operands.by_ref().take(2).collect(),
+                                )
+                            })
+                            .collect();
+                        Self::new("map", span, entries)
+                    }
+                    Expression::Error(error) => {
+                        Self::leaf(format_args!("error {:?}", error.kind.to_string()), span)
+                    }
+                };
+                finished.push(tree);
+            }
+            finished.pop().expect("the expression's tree was built")
+        }
+
+        /// Writes `(head span` with each child on its own line at `indent + 1`, then `)`. Nodes
+        /// are written from a stack, so that a deep tree can't overflow the native stack.
+        pub fn write_sexp(&self, out: &mut String, indent: usize) {
+            enum Work<'t> {
+                Open(&'t Tree, usize),
+                NewLine(usize),
+                Close,
+            }
+
+            let mut work = vec![Work::Open(self, indent)];
+            while let Some(item) = work.pop() {
+                let (tree, indent) = match item {
+                    Work::Open(tree, indent) => (tree, indent),
+                    Work::NewLine(indent) => {
+                        out.push('\n');
+                        out.push_str(&"  ".repeat(indent));
+                        continue;
+                    }
+                    Work::Close => {
+                        out.push(')');
+                        continue;
+                    }
+                };
+                let _ = write!(out, "({} {:?}", tree.head, tree.span);
+                work.push(Work::Close);
+                // Pushed in reverse, so that they're written in order.
+                for child in tree.children.iter().rev() {
+                    work.push(Work::Open(child, indent + 1));
+                    work.push(Work::NewLine(indent + 1));
+                }
+            }
+        }
+
+        /// Writes the node on a line starting with `first`, and its children on lines starting with
+        /// `rest` and a branch leading to them. Nodes are written from a stack, like in
+        /// [`write_sexp`](Self::write_sexp).
+        pub fn write_tree(&self, out: &mut String, first: &str, rest: &str) {
+            let mut work = vec![(self, first.to_string(), rest.to_string())];
+            while let Some((tree, first, rest)) = work.pop() {
+                let _ = writeln!(out, "{first}{} {:?}", tree.head, tree.span);
+                // Pushed in reverse, so that they're written in order.
+                for (i, child) in tree.children.iter().enumerate().rev() {
+                    if i + 1 == tree.children.len() {
+                        work.push((child, format!("{rest}└── "), format!("{rest}    ")));
+                    } else {
+                        work.push((child, format!("{rest}├── "), format!("{rest}│   ")));
+                    }
+                }
+            }
+        }
+    }
+
+    impl Drop for Tree {
+        /// Drops descendants one at a time from a stack, so that dropping a deep tree can't
+        /// overflow the native stack.
+        fn drop(&mut self) {
+            let mut stack = std::mem::take(&mut self.children);
+            while let Some(mut tree) = stack.pop() {
+                stack.append(&mut tree.children);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parse::parse_program;
+
+    #[test]
+    fn deep_programs_are_printed_without_recursing() {
+        let source = format!("print({})", vec!["1"; 1000].join(" + "));
+        let program = parse_program(&source).unwrap();
+        // Far too small a stack to print a thousand nested expressions by recursing.
+        let (sexp, text, tree) = std::thread::scope(|scope| {
+            std::thread::Builder::new()
+                .stack_size(64 * 1024)
+                .spawn_scoped(scope, || {
+                    (program.to_sexp(), program.to_string(), program.debug_tree())
+                })
+                .unwrap()
+                .join()
+                .unwrap()
+        });
+        assert_eq!(sexp.matches("(infix +").count(), 999);
+        assert!(text.starts_with(&format!("print({}1 + 1)", "(".repeat(999))));
+        assert_eq!(tree.matches("infix +").count(), 999);
+    }
+}
@@ -0,0 +1,41 @@
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+/// Marks the end of an executable produced by [`build`].
+const MAGIC: &[u8; 8] = b"MONKEY\0\0";
+/// The source length followed by [`MAGIC`].
+const TRAILER_LEN: usize = 16;
+
+/// Copies the running executable to `output` with `script`'s source appended to it, so that
+/// `output` runs the script instead of acting as the usual CLI.
+pub fn build(script: &Path, output: &Path) -> std::io::Result<()> {
+    let source = std::fs::read_to_string(script)?;
+    std::fs::copy(std::env::current_exe()?, output)?;
+    let mut file = File::options().append(true).open(output)?;
+    file.write_all(source.as_bytes())?;
+    file.write_all(&(source.len() as u64).to_le_bytes())?;
+    file.write_all(MAGIC)
+}
+
+/// Returns the source embedded in the running executable by [`build`], if there is any.
+pub fn embedded_source() -> Option<String> {
+    let mut file = File::open(std::env::current_exe().ok()?).ok()?;
+    file.seek(SeekFrom::End(-(TRAILER_LEN as i64))).ok()?;
+    let mut trailer = [0; TRAILER_LEN];
+    file.read_exact(&mut trailer).ok()?;
+
+    let (len, magic) = trailer.split_at(8);
+    if magic != MAGIC {
+        return None;
+    }
+    let len = u64::from_le_bytes(len.try_into().ok()?);
+
+    file.seek(SeekFrom::End(-(TRAILER_LEN as i64) - len as i64))
+        .ok()?;
+    let mut source = String::new();
+    file.take(len).read_to_string(&mut source).ok()?;
+    Some(source)
+}
@@ -1,11 +1,142 @@
-use clap::Parser;
-use std::path::PathBuf;
+use clap::{ArgAction, ArgGroup, Parser, Subcommand, ValueEnum};
+use monkey::{lint::Lint, report::ColorChoice};
+use std::{num::NonZeroUsize, path::PathBuf};
 
 #[derive(Parser)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, args_conflicts_with_subcommands = true)]
+#[command(
+    after_help = "Exits with status 1 if the program fails to parse or ends in an error, \
+                      or 101 if the interpreter itself crashes."
+)]
+#[command(group = ArgGroup::new("input").args(["file", "eval"]).multiple(true))]
 pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    /// Script to run, or `-` to read it from standard input
     #[arg()]
     pub file: Option<PathBuf>,
+    /// Arguments for the script, available from `args()`. Everything after the first of them, or
+    /// after `--`, is passed on even if it looks like an option
+    #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+    pub args: Vec<String>,
+    /// Run the given code instead of a file. Positional arguments are all passed to it
+    #[arg(short, long)]
+    pub eval: Option<String>,
+    /// Evaluate a file and then start the REPL with its bindings. Can be given more than once
+    #[arg(short, long, value_name = "FILE", conflicts_with = "input")]
+    pub load: Vec<PathBuf>,
+    /// Maximum number of expressions to evaluate (per input in the REPL)
+    #[arg(long)]
+    pub max_steps: Option<usize>,
+    /// Maximum depth of nested function calls. Unlimited by default, other than by the stack
+    #[arg(long, global = true)]
+    pub max_depth: Option<usize>,
+    /// Size in MiB of the native stack that scripts are evaluated on, which limits how deeply
+    /// they can recurse
+    #[arg(long, value_name = "MIB", default_value_t = DEFAULT_STACK_SIZE, global = true)]
+    pub stack_size: NonZeroUsize,
+    /// Print an intermediate representation of the file instead of running it
+    #[arg(long, value_enum, requires = "input")]
+    pub emit: Option<Emit>,
+    /// How to print the AST with `--emit=ast`
+    #[arg(long, value_enum, default_value_t)]
+    pub format: AstFormat,
+    /// When to color error reports. Defaults to `auto`, or in the REPL to `color` from its
+    /// config.toml
+    #[arg(long, value_enum, global = true)]
+    pub color: Option<ColorChoice>,
+    /// Log what the interpreter is doing to stderr. Repeat for more detail. `MONKEY_LOG` takes
+    /// precedence, and accepts filters like `monkey::eval=trace`
+    #[arg(short, long, action = ArgAction::Count, global = true)]
+    pub verbose: u8,
+    /// Print how long parsing and running the script took, and how much work running it was
+    #[arg(long)]
+    pub time: bool,
+    /// Treat warnings as errors and disallow looking up keys that aren't in a map. Scripts can
+    /// also opt in with a `// strict` comment before their first statement
+    #[arg(long)]
+    pub strict: bool,
+    /// Don't warn about the given kind of mistake
+    #[arg(short = 'A', long, value_enum)]
+    pub allow: Vec<Lint>,
+}
+
+/// Enough for a couple thousand nested calls in a debug build. The stack is only backed by memory
+/// as it's used.
+const DEFAULT_STACK_SIZE: NonZeroUsize = NonZeroUsize::new(64).unwrap();
+
+impl Args {
+    /// How much of the stack that scripts are evaluated on they may use for function calls. The
+    /// rest is left for the deepest call itself, and whatever it calls into.
+    pub fn max_stack(&self) -> usize {
+        self.stack_size.get() * 1024 * 1024 / 8 * 7
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Emit {
+    Ast,
+    Tokens,
+}
+
+#[derive(Clone, Copy, Default, ValueEnum)]
+pub enum AstFormat {
+    /// S-expressions with the span of every node
+    #[default]
+    Sexp,
+    /// The program as source code, with infix expressions parenthesized
+    Source,
+    /// An indented tree of node kinds and spans
+    Tree,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Package a script into a standalone executable
+    Build {
+        file: PathBuf,
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+    /// Run a script several times and report how long it took
+    Bench {
+        file: PathBuf,
+        /// How many times to run the script
+        #[arg(short = 'n', long, default_value_t = 10)]
+        runs: usize,
+        /// Arguments for the script
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+    /// Report errors and warnings in a script without running it
+    Check {
+        /// Script to check, or `-` to read it from standard input
+        file: PathBuf,
+        /// Treat warnings as errors
+        #[arg(long)]
+        strict: bool,
+        /// Don't warn about the given kind of mistake
+        #[arg(short = 'A', long, value_enum)]
+        allow: Vec<Lint>,
+    },
+    /// Run the examples in a directory and compare what they print with their `.out` files
+    Test {
+        /// Directory of `.mk` examples, each next to a `.out` file with its expected output
+        #[arg(
+            long,
+            value_name = "DIR",
+            num_args = 0..=1,
+            default_missing_value = "examples",
+            required = true
+        )]
+        examples: Option<PathBuf>,
+        /// Write each example's output to its `.out` file instead of comparing them
+        #[arg(long)]
+        bless: bool,
+    },
+    /// Start a language server that editors talk to over standard input and output
+    #[cfg(feature = "lsp")]
+    Lsp,
 }
 
 pub fn parse() -> Args {
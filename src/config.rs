@@ -0,0 +1,61 @@
+//! Settings for the REPL, read from `monkey` in the user's config directory. Besides
+//! `config.toml`, the directory can hold an `init.mk` script that's run at the start of every
+//! session.
+
+use monkey::report::ColorChoice;
+use serde::Deserialize;
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+/// The contents of `config.toml`.
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Shown before each line of input.
+    pub prompt: String,
+    /// When to color error reports, unless `--color` is given.
+    pub color: Option<ColorChoice>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            prompt: ">> ".to_string(),
+            color: None,
+        }
+    }
+}
+
+/// `$XDG_CONFIG_HOME/monkey`, or `~/.config/monkey` if that isn't set.
+pub fn dir() -> Option<PathBuf> {
+    let base = match std::env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("monkey"))
+}
+
+/// Reads `config.toml` from `dir`. If it's missing the defaults are used, and if it's invalid
+/// they're used after a warning.
+pub fn load(dir: &Path) -> Config {
+    let path = dir.join("config.toml");
+    let text = read(&path).unwrap_or_default();
+    toml::from_str(&text).unwrap_or_else(|err| {
+        eprintln!("Warning: ignoring {}: {err}", path.display());
+        Config::default()
+    })
+}
+
+/// Reads the file at `path`, warning about any error other than it not existing.
+pub fn read(path: &Path) -> Option<String> {
+    match std::fs::read_to_string(path) {
+        Ok(text) => Some(text),
+        Err(err) if err.kind() == ErrorKind::NotFound => None,
+        Err(err) => {
+            eprintln!("Warning: couldn't read {}: {err}", path.display());
+            None
+        }
+    }
+}
@@ -0,0 +1,162 @@
+//! Conversions between values and Rust types, for passing data into programs and getting typed
+//! results back out.
+//!
+//! ```
+//! use monkey::{Environment, Value, parse_program};
+//!
+//! let mut env = Environment::default();
+//! env.register("sum", |args| {
+//!     let numbers: Vec<i64> = args.first().cloned().unwrap_or(Value::Null).try_into()?;
+//!     Ok(numbers.into_iter().sum::<i64>().into())
+//! });
+//! let program = parse_program("[sum([1, 2, 3]), null]").unwrap();
+//! let result: Vec<Option<i64>> = env.eval(program).unwrap().try_into().unwrap();
+//! assert_eq!(result, [Some(6), None]);
+//! ```
+
+use crate::{
+    sync::Rc,
+    value::{Type, Value},
+};
+use std::collections::HashMap;
+
+/// A value of a different type than a conversion from it expected.
+#[derive(thiserror::Error, Debug, Clone, Copy)]
+#[error("expected {expected}, found {found}")]
+pub struct WrongType {
+    pub expected: Type,
+    pub found: Type,
+}
+
+impl WrongType {
+    fn new(expected: Type, found: &Value) -> Self {
+        Self {
+            expected,
+            found: found.into(),
+        }
+    }
+}
+
+impl From<i64> for Value<'_> {
+    fn from(value: i64) -> Self {
+        Self::Int(value)
+    }
+}
+
+impl From<bool> for Value<'_> {
+    fn from(value: bool) -> Self {
+        Self::Bool(value)
+    }
+}
+
+impl From<String> for Value<'_> {
+    fn from(value: String) -> Self {
+        Self::String(Rc::new(value))
+    }
+}
+
+impl From<&str> for Value<'_> {
+    fn from(value: &str) -> Self {
+        Self::String(Rc::new(value.to_string()))
+    }
+}
+
+impl<'a, T: Into<Value<'a>>> From<Vec<T>> for Value<'a> {
+    fn from(value: Vec<T>) -> Self {
+        Self::Array(Rc::new(value.into_iter().map(Into::into).collect()))
+    }
+}
+
+impl<'a, T: Into<Value<'a>>> From<HashMap<String, T>> for Value<'a> {
+    fn from(value: HashMap<String, T>) -> Self {
+        Self::Map(Rc::new(
+            value
+                .into_iter()
+                .map(|(k, v)| (Self::from(k), v.into()))
+                .collect(),
+        ))
+    }
+}
+
+impl<'a, T: Into<Value<'a>>> From<Option<T>> for Value<'a> {
+    /// `None` becomes null.
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Self::Null, Into::into)
+    }
+}
+
+impl TryFrom<Value<'_>> for i64 {
+    type Error = WrongType;
+
+    fn try_from(value: Value<'_>) -> Result<Self, Self::Error> {
+        match value {
+            Value::Int(i) => Ok(i),
+            value => Err(WrongType::new(Type::Int, &value)),
+        }
+    }
+}
+
+impl TryFrom<Value<'_>> for bool {
+    type Error = WrongType;
+
+    /// Only converts booleans. Use [`Value::truthy`] for the truthiness of other values.
+    fn try_from(value: Value<'_>) -> Result<Self, Self::Error> {
+        match value {
+            Value::Bool(b) => Ok(b),
+            value => Err(WrongType::new(Type::Bool, &value)),
+        }
+    }
+}
+
+impl TryFrom<Value<'_>> for String {
+    type Error = WrongType;
+
+    fn try_from(value: Value<'_>) -> Result<Self, Self::Error> {
+        match value {
+            Value::String(s) => Ok(Rc::unwrap_or_clone(s)),
+            value => Err(WrongType::new(Type::String, &value)),
+        }
+    }
+}
+
+impl<'a, T: TryFrom<Value<'a>, Error = WrongType>> TryFrom<Value<'a>> for Vec<T> {
+    type Error = WrongType;
+
+    /// Fails on the first element that doesn't convert.
+    fn try_from(value: Value<'a>) -> Result<Self, Self::Error> {
+        match value {
+            Value::Array(a) => Rc::unwrap_or_clone(a)
+                .into_iter()
+                .map(T::try_from)
+                .collect(),
+            value => Err(WrongType::new(Type::Array, &value)),
+        }
+    }
+}
+
+impl<'a, T: TryFrom<Value<'a>, Error = WrongType>> TryFrom<Value<'a>> for HashMap<String, T> {
+    type Error = WrongType;
+
+    /// Fails on the first key that isn't a string, or value that doesn't convert.
+    fn try_from(value: Value<'a>) -> Result<Self, Self::Error> {
+        match value {
+            Value::Map(m) => Rc::unwrap_or_clone(m)
+                .into_iter()
+                .map(|(k, v)| Ok((String::try_from(k)?, T::try_from(v)?)))
+                .collect(),
+            value => Err(WrongType::new(Type::Map, &value)),
+        }
+    }
+}
+
+impl<'a, T: TryFrom<Value<'a>, Error = WrongType>> TryFrom<Value<'a>> for Option<T> {
+    type Error = WrongType;
+
+    /// Null becomes `None`.
+    fn try_from(value: Value<'a>) -> Result<Self, Self::Error> {
+        match value {
+            Value::Null => Ok(None),
+            value => T::try_from(value).map(Some),
+        }
+    }
+}
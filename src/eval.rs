@@ -1,5 +1,20 @@
-use crate::{ast::*, intrinsic::find_intrinsic, value::*};
-use std::{collections::HashMap, rc::Rc};
+//! The tree-walking evaluator, which runs programs directly from their syntax tree.
+
+use crate::{
+    ast::*,
+    intrinsic::{Argument, HostFunction, HostResult, INTRINSICS, find_intrinsic},
+    report::Source,
+    sync::{MaybeSync, Rc, RefCell, Weak},
+    value::*,
+};
+use std::{
+    cell::Cell,
+    collections::HashMap,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+};
 
 pub type Result<'a, T, E = Error<'a>> = std::result::Result<T, E>;
 
@@ -8,205 +23,727 @@ pub type Result<'a, T, E = Error<'a>> = std::result::Result<T, E>;
 pub struct Error<'a> {
     pub span: Span,
     pub kind: ErrorKind<'a>,
+    /// Spans of the calls the error propagated through, innermost first.
+    pub trace: Vec<Span>,
 }
 
 #[derive(thiserror::Error, Debug)]
 pub enum ErrorKind<'a> {
-    #[error("unknown identifier: {0}")]
-    UnknownIdentifier(Identifier<'a>),
+    #[error("unknown identifier: {name}")]
+    UnknownIdentifier {
+        name: Identifier<'a>,
+        /// A similar name that is defined.
+        suggestion: Option<String>,
+    },
     #[error("cannot negate {0}")]
     InvalidNeg(Type),
-    #[error("cannot use {0} on {1} and {2}")]
-    InvalidInfix(InfixOperator, Type, Type),
+    #[error("cannot use {operator} on {} and {}", left.ty, right.ty)]
+    InvalidInfix {
+        operator: InfixOperator,
+        left: Operand,
+        right: Operand,
+    },
+    #[error("division by zero")]
+    DivisionByZero,
+    #[error("integer overflow")]
+    Overflow,
     #[error("attempted to call non-function ({0})")]
     NonFunction(Type),
     #[error(
         "attempted to call function with wrong number of arguments (expected {expected}, found {found})"
     )]
     WrongNumberOfArguments { expected: usize, found: usize },
+    #[error("maximum recursion depth ({0}) exceeded")]
+    MaxDepthExceeded(usize),
+    #[error("maximum recursion depth exceeded, after using {0} bytes of stack")]
+    StackExhausted(usize),
+    #[error("step limit exceeded")]
+    StepLimitExceeded,
+    #[error("interrupted")]
+    Interrupted,
+    #[error("expected {expected} argument, found {found}")]
+    WrongArgumentType { expected: Type, found: Type },
     #[error("index out of bounds; len was {len} but index was {index}")]
     IndexOutOfBounds { len: usize, index: i64 },
     #[error("cannot index {0} with {1}")]
     InvalidIndex(Type, Type),
+    #[error("map has no entry for {0}")]
+    MissingKey(String),
     #[error("cannot use {0} as a map key")]
     InvalidMapKey(Type),
+    #[error("range step cannot be zero")]
+    ZeroStep,
+    #[error("size must be positive, found {0}")]
+    NonPositiveSize(i64),
+    #[error("{0} is not a valid byte")]
+    InvalidByte(i64),
+    #[error("{0} is not a valid exit code")]
+    InvalidExitCode(i64),
+    #[error("{0} has no length")]
+    NoLength(Type),
+    #[error("slice start ({start}) is after its end ({end})")]
+    InvertedSlice { start: usize, end: usize },
+    #[error("{0}")]
+    Io(std::io::Error),
+    #[error("invalid environment variable {0:?}")]
+    InvalidEnvVar(String),
+    #[error("cannot sleep for a negative duration ({0}ms)")]
+    NegativeDuration(i64),
+    #[error("expected a single character, found {0:?}")]
+    NotAChar(String),
+    #[cfg(feature = "regex")]
+    #[error("invalid regex: {0}")]
+    InvalidRegex(regex::Error),
+    #[cfg(feature = "json")]
+    #[error("invalid JSON: {0}")]
+    InvalidJson(serde_json::Error),
+    #[cfg(feature = "json")]
+    #[error(transparent)]
+    NotJson(crate::json::NotJson),
+    #[cfg(feature = "http")]
+    #[error("HTTP request failed: {0}")]
+    Http(Box<ureq::Error>),
+    /// Returned by a [`HostFunction`].
+    #[error("{0}")]
+    Host(Box<dyn std::error::Error + Send + Sync>),
+    /// Raised from an error value.
+    #[error("{0}")]
+    Raised(String),
+    /// Not a real error; unwinds evaluation to the enclosing function call, which evaluates to the
+    /// given value.
+    #[error("returned from outside a function")]
+    Return(Value<'a>),
+    /// Not a real error; unwinds evaluation so the caller can exit with the given code.
+    #[error("exited with code {0}")]
+    Exit(i32),
+}
+
+/// One side of an infix operation, kept so that errors can point at each operand separately.
+#[derive(Debug, Clone, Copy)]
+pub struct Operand {
+    pub span: Span,
+    pub ty: Type,
 }
 
 impl ErrorKind<'_> {
+    /// Labels to show in place of the one on the error's whole span.
+    pub fn labels(&self) -> Vec<(Span, String)> {
+        match self {
+            Self::InvalidInfix { left, right, .. } => vec![
+                (left.span, format!("this is {}", left.ty)),
+                (right.span, format!("this is {}", right.ty)),
+            ],
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn help(&self) -> Option<String> {
+        match self {
+            Self::UnknownIdentifier {
+                suggestion: Some(suggestion),
+                ..
+            } => Some(format!("did you mean `{suggestion}`?")),
+            _ => None,
+        }
+    }
+
     pub fn note(&self) -> Option<String> {
         match self {
             Self::InvalidNeg(_) => Some("Only integers can be negated".to_string()),
             Self::IndexOutOfBounds { index: ..0, .. } => {
                 Some("Index cannot be negative".to_string())
             }
-            Self::InvalidMapKey(_) => {
-                Some("Only strings, integers, and booleans can be map keys".to_string())
+            Self::InvalidMapKey(_) => Some(
+                "Only strings, bytes, integers, booleans, and arrays of them can be map keys"
+                    .to_string(),
+            ),
+            Self::InvalidEnvVar(_) => Some(
+                "Names must be non-empty and cannot contain `=` or NUL; values cannot contain NUL"
+                    .to_string(),
+            ),
+            Self::MissingKey(_) => Some(
+                "In strict mode, looking up a missing key is an error rather than `null`"
+                    .to_string(),
+            ),
+            Self::InvalidByte(_) => Some("Bytes must be between 0 and 255".to_string()),
+            Self::Overflow => Some(format!(
+                "Integers must be between {} and {}",
+                i64::MIN,
+                i64::MAX
+            )),
+            Self::InvalidExitCode(_) => Some(format!(
+                "Exit codes must be between {} and {}",
+                i32::MIN,
+                i32::MAX
+            )),
+            Self::StackExhausted(_) => Some(
+                "Each call uses native stack, so recursing deeper needs a bigger stack".to_string(),
+            ),
+            Self::InvalidInfix {
+                operator: InfixOperator::Add,
+                ..
+            } => Some("`+` can only be used on two integers or two strings".to_string()),
+            Self::InvalidInfix { operator, .. } => {
+                Some(format!("`{operator}` can only be used on two integers"))
             }
             _ => None,
         }
     }
 }
 
-impl Error<'_> {
-    pub fn report(&self, input: &str) {
-        use ariadne::{Color, Label, Report, ReportKind, Source};
+impl<'a> Error<'a> {
+    pub fn new(span: Span, kind: ErrorKind<'a>) -> Self {
+        Self {
+            span,
+            kind,
+            trace: Vec::new(),
+        }
+    }
+
+    pub fn report(&self, source: Source) {
+        self.write_report(source, std::io::stderr());
+    }
 
-        let mut builder = Report::build(ReportKind::Error, self.span)
-            .with_message(&self.kind)
-            .with_label(Label::new(self.span).with_color(Color::Red));
+    /// Like [`report`](Self::report), but writes to `out` instead of stderr.
+    pub fn write_report(&self, source: Source, out: impl std::io::Write) {
+        use ariadne::{Color, Label, ReportKind};
 
+        let labels = self.kind.labels();
+        let frames = self.trace_frames();
+        let hidden = self.trace.len() - frames.iter().map(|(_, count)| count).sum::<usize>();
+        let mut builder = source
+            .build_report(ReportKind::Error, self.span)
+            .with_message(&self.kind);
+        if labels.is_empty() {
+            builder = builder.with_label(Label::new(source.span(self.span)).with_color(Color::Red));
+        }
+        builder = builder
+            .with_labels(labels.into_iter().map(|(span, message)| {
+                Label::new(source.span(span))
+                    .with_message(message)
+                    .with_color(Color::Red)
+            }))
+            .with_labels(frames.into_iter().enumerate().map(|(i, (span, count))| {
+                let message = match count {
+                    1 => format!("{}: called here", i + 1),
+                    count => format!("{}: called here (×{count})", i + 1),
+                };
+                Label::new(source.span(span))
+                    .with_message(message)
+                    .with_color(Color::Yellow)
+            }));
+
+        if let Some(help) = self.kind.help() {
+            builder = builder.with_help(help);
+        }
         if let Some(note) = self.kind.note() {
             builder = builder.with_note(note);
         }
+        if hidden > 0 {
+            builder = builder.with_note(format!("… {hidden} more calls"));
+        }
+
+        source.write(builder, out);
+    }
+
+    /// The calls in the trace, innermost first, with consecutive calls from the same place merged
+    /// into one frame along with how many of them there were. Only the first
+    /// [`MAX_TRACE_FRAMES`] frames are included.
+    fn trace_frames(&self) -> Vec<(Span, usize)> {
+        let mut frames: Vec<(Span, usize)> = Vec::new();
+        for &span in &self.trace {
+            if let Some((last, count)) = frames.last_mut()
+                && *last == span
+            {
+                *count += 1;
+            } else if frames.len() < MAX_TRACE_FRAMES {
+                frames.push((span, 1));
+            } else {
+                break;
+            }
+        }
+        frames
+    }
+}
+
+/// How many frames of a call trace reports show. Recursion repeats the same call, which is shown
+/// once, but mutual recursion can still make traces too long to read.
+const MAX_TRACE_FRAMES: usize = 8;
+
+impl From<ErrorValue> for Error<'_> {
+    fn from(error: ErrorValue) -> Self {
+        Self {
+            span: error.span,
+            kind: ErrorKind::Raised(error.message),
+            trace: error.trace,
+        }
+    }
+}
+
+impl<'a> From<Error<'a>> for ErrorValue {
+    fn from(error: Error<'a>) -> Self {
+        Self {
+            message: error.kind.to_string(),
+            span: error.span,
+            trace: error.trace,
+        }
+    }
+}
+
+/// Evaluates to the given value, or returns it early if it's an error value, so that error values
+/// propagate out of any expression that operates on them.
+macro_rules! propagate_error {
+    ($value:expr) => {
+        match $value {
+            error @ Value::Error(_) => return Ok(error),
+            value => value,
+        }
+    };
+}
+
+/// Finds the candidate most similar to `name`, if any is similar enough to be a likely typo. Ties
+/// go to the alphabetically first candidate, so that suggestions don't depend on hash order.
+fn closest_name<'c>(name: &str, candidates: impl IntoIterator<Item = &'c str>) -> Option<&'c str> {
+    let max_distance = (name.chars().count() / 3).max(1);
+    candidates
+        .into_iter()
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
+/// The number of single-character insertions, deletions, substitutions, and adjacent
+/// transpositions needed to turn `a` into `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let (a, b): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+    // distances[i][j] is the distance between the first i characters of a and the first j of b.
+    let mut distances = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in distances.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    distances[0] = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            let mut distance = (distances[i - 1][j] + 1)
+                .min(distances[i][j - 1] + 1)
+                .min(distances[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distance = distance.min(distances[i - 2][j - 2] + 1);
+            }
+            distances[i][j] = distance;
+        }
+    }
+    distances[a.len()][b.len()]
+}
+
+/// Half of the 2 MiB stack that Rust gives the threads it spawns, leaving the rest for whatever is
+/// running when the limit is reached. How many calls fit in it depends on the build, since debug
+/// builds use several times more stack per call.
+pub const DEFAULT_MAX_STACK: usize = 1024 * 1024;
+
+thread_local! {
+    /// Roughly where the native stack was when the outermost evaluation on this thread started.
+    static STACK_BASE: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+/// Roughly where the native stack is now.
+fn stack_position() -> usize {
+    let marker = 0u8;
+    std::hint::black_box(&marker) as *const u8 as usize
+}
+
+/// Marks where the native stack was when the outermost evaluation on this thread started, for as
+/// long as it's alive, so that nested calls can measure how much stack they've used.
+struct StackBase {
+    outermost: bool,
+}
+
+impl StackBase {
+    fn enter() -> Self {
+        let outermost = STACK_BASE.get().is_none();
+        if outermost {
+            STACK_BASE.set(Some(stack_position()));
+        }
+        Self { outermost }
+    }
+
+    /// Bytes of stack used since the outermost evaluation on this thread started.
+    fn used() -> usize {
+        STACK_BASE
+            .get()
+            .map_or(0, |base| base.abs_diff(stack_position()))
+    }
+}
 
-        builder
-            .finish()
-            .eprint(("input", Source::from(input)))
-            .unwrap();
+impl Drop for StackBase {
+    fn drop(&mut self) {
+        if self.outermost {
+            STACK_BASE.set(None);
+        }
     }
 }
 
 #[derive(Default)]
-pub struct Environment<'a> {
+pub struct Scope<'a> {
     pub locals: HashMap<Identifier<'a>, Value<'a>>,
+    pub parent: Option<Rc<RefCell<Scope<'a>>>>,
+}
+
+impl<'a> Scope<'a> {
+    /// Every name visible from this scope.
+    fn names(&self) -> Vec<&'a str> {
+        let mut names: Vec<_> = self.locals.keys().map(|ident| ident.name).collect();
+        if let Some(parent) = &self.parent {
+            names.extend(parent.borrow().names());
+        }
+        names
+    }
+
+    pub fn get(&self, name: &Identifier<'a>) -> Option<Value<'a>> {
+        match self.locals.get(name) {
+            Some(value) => Some(value.clone()),
+            None => self.parent.as_ref()?.borrow().get(name),
+        }
+    }
+}
+
+/// The scopes that functions were defined in, shared by an environment and the ones made for its
+/// calls.
+///
+/// A function holds the scope it was defined in, and that scope usually holds the function, either
+/// because it was bound there or because it refers to itself. Reference counting can't free such
+/// cycles, so once the last environment sharing this is dropped, every scope in it that's still
+/// alive is cleared. Functions that outlive their environment can still be called, but the names
+/// they were defined among are gone.
+#[derive(Default)]
+pub struct Scopes<'a>(RefCell<Vec<Weak<RefCell<Scope<'a>>>>>);
+
+impl<'a> Scopes<'a> {
+    fn insert(&self, scope: &Rc<RefCell<Scope<'a>>>) {
+        let mut scopes = self.0.borrow_mut();
+        // Functions are usually defined several at a time in the same scope.
+        if scopes
+            .last()
+            .is_some_and(|last| std::ptr::eq(last.as_ptr(), Rc::as_ptr(scope)))
+        {
+            return;
+        }
+        // Forget scopes that have been freed before growing, so that this stays proportional to
+        // the number of scopes alive.
+        if scopes.len() == scopes.capacity() {
+            scopes.retain(|scope| scope.strong_count() > 0);
+        }
+        scopes.push(Rc::downgrade(scope));
+    }
+}
+
+impl Drop for Scopes<'_> {
+    fn drop(&mut self) {
+        for scope in std::mem::take(&mut *self.0.borrow_mut()) {
+            if let Some(scope) = scope.upgrade() {
+                // Taken out first, since dropping the locals can free other scopes.
+                let locals = std::mem::take(&mut scope.borrow_mut().locals);
+                drop(locals);
+            }
+        }
+    }
+}
+
+/// The state programs run in: their bindings, and the limits and settings they run under.
+pub struct Environment<'a> {
+    pub scope: Rc<RefCell<Scope<'a>>>,
+    pub scopes: Rc<Scopes<'a>>,
+    pub args: Rc<[String]>,
+    /// Variables set with `set_env`. They take precedence over the process's environment, which
+    /// is never changed, and are passed on to programs that `exec` runs.
+    pub env_vars: Rc<RefCell<HashMap<String, String>>>,
+    /// Number of function calls this environment is nested in.
+    pub depth: usize,
+    /// Unlimited by default, since [`max_stack`](Self::max_stack) is what keeps recursion from
+    /// overflowing the stack.
+    pub max_depth: usize,
+    /// Bytes of native stack that nested calls may use, counted from where the outermost
+    /// evaluation on the thread started. It has to leave room in the thread's stack for the
+    /// deepest call itself, so it should be well below the stack's size.
+    pub max_stack: usize,
+    /// Number of expressions that may still be evaluated, if limited.
+    pub steps_remaining: Option<usize>,
+    /// Whether strict mode is on, either from the command line or the program's pragma.
+    pub strict: bool,
+    pub stats: Stats,
+    /// Where `print`, `println`, `dbg`, and `pprint` write, if not to standard output.
+    pub output: Option<Rc<RefCell<dyn Output>>>,
+    /// Set from elsewhere, such as a Ctrl-C handler, to stop evaluation with an error. It's
+    /// cleared again once evaluation stops.
+    pub interrupt: Option<Arc<AtomicBool>>,
+    /// Functions added with [`register`](Self::register), by name.
+    pub host_functions: Rc<HashMap<String, Rc<dyn HostFunction<'a> + 'a>>>,
+}
+
+/// A writer that environments can share.
+pub trait Output: std::io::Write + MaybeSync {}
+
+impl<T: std::io::Write + MaybeSync + ?Sized> Output for T {}
+
+/// Measurements of how much work evaluation took.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Stats {
+    /// Number of expressions evaluated.
+    pub steps: usize,
+    /// Deepest function call nesting reached.
+    pub peak_depth: usize,
+}
+
+impl Default for Environment<'_> {
+    fn default() -> Self {
+        Self {
+            scope: Rc::default(),
+            scopes: Rc::default(),
+            args: Rc::default(),
+            env_vars: Rc::default(),
+            depth: 0,
+            max_depth: usize::MAX,
+            max_stack: DEFAULT_MAX_STACK,
+            steps_remaining: None,
+            strict: false,
+            stats: Stats::default(),
+            output: None,
+            interrupt: None,
+            host_functions: Rc::default(),
+        }
+    }
 }
 
 impl<'a> Environment<'a> {
+    /// Makes `function` callable from programs as `name`, the same way as intrinsics. It takes
+    /// precedence over an intrinsic with the same name, but not over bindings in the program.
+    ///
+    /// ```
+    /// use monkey::{Environment, Value, parse_program};
+    ///
+    /// let mut env = Environment::default();
+    /// env.register("double", |args| match args {
+    ///     [Value::Int(n)] => Ok(Value::Int(n * 2)),
+    ///     _ => Err("double takes one integer".into()),
+    /// });
+    /// let program = parse_program("double(21)").unwrap();
+    /// assert!(matches!(env.eval(program), Ok(Value::Int(42))));
+    /// ```
+    pub fn register(
+        &mut self,
+        name: impl Into<String>,
+        function: impl Fn(&[Value<'a>]) -> HostResult<'a> + MaybeSync + 'a,
+    ) {
+        Rc::make_mut(&mut self.host_functions).insert(name.into(), Rc::new(function));
+    }
+
+    /// Binds `name` to `value` before a program runs, as though it started with a `let` for it.
+    /// Programs can still rebind it.
+    ///
+    /// ```
+    /// use monkey::{Environment, Value, parse_program};
+    /// use std::collections::HashMap;
+    ///
+    /// let mut env = Environment::default();
+    /// env.define("limit", 3);
+    /// env.define("config", HashMap::from([("name".to_string(), "demo")]));
+    /// let program = parse_program(r#"let total = limit * 2; config["name"]"#).unwrap();
+    /// let name = env.eval(program).unwrap();
+    /// assert_eq!(String::try_from(name).unwrap(), "demo");
+    /// assert!(matches!(env.get("total"), Some(Value::Int(6))));
+    /// ```
+    pub fn define(&mut self, name: &'a str, value: impl Into<Value<'a>>) {
+        let name = Identifier {
+            name,
+            span: Span::default(),
+        };
+        self.scope.borrow_mut().locals.insert(name, value.into());
+    }
+
+    /// The value bound to `name` in this environment's scope, such as one defined by a program
+    /// it ran.
+    pub fn get(&self, name: &'a str) -> Option<Value<'a>> {
+        self.scope.borrow().get(&Identifier {
+            name,
+            span: Span::default(),
+        })
+    }
+
+    /// Runs `program` with this environment's bindings, keeping any it adds. Evaluates to the
+    /// value of its last statement, or of a top-level `return`.
     pub fn eval(&mut self, program: Program<'a>) -> Result<'a, Value<'a>> {
-        self.eval_statements(program.statements)
+        let _base = StackBase::enter();
+        self.strict |= program.strict;
+        match self.eval_statements(program.statements) {
+            Err(Error {
+                kind: ErrorKind::Return(value),
+                ..
+            }) => Ok(value),
+            result => result,
+        }
     }
 
+    /// Evaluates to the value of the last statement if it's an expression without a trailing
+    /// semicolon, and to null otherwise.
     fn eval_statements(&mut self, statements: Vec<Statement<'a>>) -> Result<'a, Value<'a>> {
+        let mut value = Value::Null;
         for statement in statements {
-            if let Some(ret) = self.eval_statement(statement)? {
-                return Ok(ret);
-            }
+            value = self.eval_statement(statement)?;
         }
 
-        Ok(Value::Null)
+        Ok(value)
     }
 
-    fn eval_statement(&mut self, statement: Statement<'a>) -> Result<'a, Option<Value<'a>>> {
+    fn eval_statement(&mut self, statement: Statement<'a>) -> Result<'a, Value<'a>> {
         match statement {
             Statement::Let { name, value, .. } => {
                 let value = self.eval_expression(value, Some(name.clone()))?;
-                self.locals.insert(name, value);
-                Ok(None)
+                tracing::trace!(name = name.name, depth = self.depth, "bind");
+                self.scope.borrow_mut().locals.insert(name, value);
+                Ok(Value::Null)
             }
-            Statement::Return { value, .. } | Statement::Expression { value, semi: false } => {
-                self.eval_expression(value, None).map(Some)
+            Statement::Return { return_span, value } => {
+                let span = return_span.join(value.span());
+                let value = self.eval_expression(value, None)?;
+                Err(Error::new(span, ErrorKind::Return(value)))
             }
+            Statement::Expression { value, semi: false } => self.eval_expression(value, None),
             Statement::Expression { value, .. } => {
                 let _ = self.eval_expression(value, None)?;
-                Ok(None)
+                Ok(Value::Null)
             }
+            Statement::Error(_) => unreachable!("programs with syntax errors aren't evaluated"),
+        }
+    }
+
+    /// Consumes one step of the evaluation budget, if there is one.
+    fn step(&mut self, span: Span) -> Result<'a, ()> {
+        self.stats.steps += 1;
+        if let Some(interrupt) = &self.interrupt
+            && interrupt.swap(false, Ordering::Relaxed)
+        {
+            return Err(Error::new(span, ErrorKind::Interrupted));
+        }
+        match &mut self.steps_remaining {
+            Some(0) => Err(Error::new(span, ErrorKind::StepLimitExceeded)),
+            Some(steps) => {
+                *steps -= 1;
+                Ok(())
+            }
+            None => Ok(()),
         }
     }
 
     fn eval_expression(
         &mut self,
-        expression: Expression<'a>,
+        mut expression: Expression<'a>,
         name: Option<Identifier<'a>>,
     ) -> Result<'a, Value<'a>> {
+        if let Expression::Prefix { .. } | Expression::Infix { .. } = expression {
+            return self.eval_operators(expression);
+        }
         let span = expression.span();
-        match expression {
-            Expression::Identifier(ident) => self.locals.get(&ident).cloned().ok_or(Error {
-                span,
-                kind: ErrorKind::UnknownIdentifier(ident),
-            }),
-            Expression::Integer { value, .. } => Ok(Value::Int(value)),
-            Expression::Prefix { prefix, right } => {
-                let right = self.eval_expression(*right, None)?;
-                match (prefix.operator, right) {
-                    (PrefixOperator::Neg, Value::Int(value)) => Ok(Value::Int(-value)),
-                    (PrefixOperator::Not, right) => Ok(Value::Bool(!right.truthy())),
-                    (PrefixOperator::Neg, right) => Err(Error {
+        self.step(span)?;
+        // Expressions implement `Drop`, so their parts are taken out of them instead of being
+        // moved out by destructuring.
+        match &mut expression {
+            Expression::Identifier(ident) => {
+                let ident = ident.clone();
+                let scope = self.scope.borrow();
+                scope.get(&ident).ok_or_else(|| {
+                    let host_functions = self.host_functions.keys().map(String::as_str);
+                    let intrinsics = INTRINSICS.iter().map(|(name, _)| *name);
+                    let suggestion = closest_name(
+                        ident.name,
+                        scope
+                            .names()
+                            .into_iter()
+                            .chain(host_functions)
+                            .chain(intrinsics),
+                    )
+                    .map(str::to_string);
+                    Error::new(
                         span,
-                        kind: ErrorKind::InvalidNeg(right.into()),
-                    }),
-                }
+                        ErrorKind::UnknownIdentifier {
+                            name: ident,
+                            suggestion,
+                        },
+                    )
+                })
             }
-            Expression::Infix {
-                left,
-                operator,
-                right,
-            } => {
-                let left = self.eval_expression(*left, None)?;
-                let right = self.eval_expression(*right, None)?;
-                match (left, operator, right) {
-                    (left, InfixOperator::Eq, right) => Ok(Value::Bool(left == right)),
-                    (left, InfixOperator::Neq, right) => Ok(Value::Bool(left != right)),
-                    (Value::Int(l), _, Value::Int(r)) => match operator {
-                        InfixOperator::Add => Ok(Value::Int(l + r)),
-                        InfixOperator::Sub => Ok(Value::Int(l - r)),
-                        InfixOperator::Mul => Ok(Value::Int(l * r)),
-                        InfixOperator::Div => Ok(Value::Int(l / r)),
-                        InfixOperator::LT => Ok(Value::Bool(l < r)),
-                        InfixOperator::GT => Ok(Value::Bool(l > r)),
-                        _ => Err(Error {
-                            span,
-                            kind: ErrorKind::InvalidInfix(operator, Type::Int, Type::Int),
-                        }),
-                    },
-                    (Value::String(l), InfixOperator::Add, Value::String(r)) => {
-                        Ok(Value::String(l + &r))
-                    }
-                    (left, _, right) => Err(Error {
-                        span,
-                        kind: ErrorKind::InvalidInfix(operator, left.into(), right.into()),
-                    }),
-                }
+            &mut Expression::Integer { value, .. } => Ok(Value::Int(value)),
+            Expression::Prefix { .. } | Expression::Infix { .. } => {
+                unreachable!("operators are evaluated by `eval_operators`")
             }
-            Expression::Boolean { value, .. } => Ok(Value::Bool(value)),
+            Expression::Error(_) => unreachable!("programs with syntax errors aren't evaluated"),
+            &mut Expression::Boolean { value, .. } => Ok(Value::Bool(value)),
             Expression::If {
                 condition,
                 consequence,
                 alternative,
                 ..
             } => {
-                let condition = self.eval_expression(*condition, None)?;
+                let condition = propagate_error!(self.eval_expression(condition.take(), None)?);
                 if condition.truthy() {
-                    self.eval_statements(consequence.statements)
+                    self.eval_statements(std::mem::take(&mut consequence.statements))
                 } else if let Some(alternative) = alternative {
-                    self.eval_statements(alternative.statements)
+                    self.eval_statements(std::mem::take(&mut alternative.statements))
                 } else {
                     Ok(Value::Null)
                 }
             }
             Expression::Function {
                 parameters, body, ..
-            } => Ok(Value::Function(Rc::new(Function {
-                name,
-                parameters,
-                body,
-            }))),
+            } => {
+                self.scopes.insert(&self.scope);
+                Ok(Value::Function(Rc::new(Function {
+                    name,
+                    parameters: std::mem::take(parameters),
+                    body: std::mem::take(body),
+                    scope: self.scope.clone(),
+                })))
+            }
             Expression::Call {
                 function,
                 arguments,
                 ..
             } => {
+                // User bindings shadow host functions, which shadow intrinsics of the same name.
                 if let Expression::Identifier(ident) = function.as_ref()
-                    && let Some(intrinsic) = find_intrinsic(ident.name)
+                    && self.scope.borrow().get(ident).is_none()
                 {
-                    return intrinsic(
-                        span,
-                        arguments
+                    if let Some(host_function) = self.host_functions.get(ident.name).cloned() {
+                        let arguments = std::mem::take(arguments)
                             .into_iter()
                             .map(|arg| self.eval_expression(arg, None))
-                            .collect::<Result<_>>()?,
-                    );
+                            .collect::<Result<Vec<_>>>()?;
+                        return host_function(&arguments)
+                            .map_err(|e| Error::new(span, ErrorKind::Host(e)));
+                    }
+                    if let Some(intrinsic) = find_intrinsic(ident.name) {
+                        let arguments = std::mem::take(arguments)
+                            .into_iter()
+                            .map(|arg| {
+                                Ok(Argument {
+                                    span: arg.span(),
+                                    value: self.eval_expression(arg, None)?,
+                                })
+                            })
+                            .collect::<Result<_>>()?;
+                        return intrinsic(self, span, arguments);
+                    }
                 }
-                let function = match self.eval_expression(*function, None)? {
+                let function = match propagate_error!(self.eval_expression(function.take(), None)?)
+                {
                     Value::Function(function) => function,
                     value => {
-                        return Err(Error {
-                            span,
-                            kind: ErrorKind::NonFunction(value.into()),
-                        });
+                        return Err(Error::new(span, ErrorKind::NonFunction(value.into())));
                     }
                 };
 
-                let arguments = arguments
+                let arguments = std::mem::take(arguments)
                     .into_iter()
                     .map(|arg| self.eval_expression(arg, None))
                     .collect::<Result<_>>()?;
@@ -214,93 +751,596 @@ impl<'a> Environment<'a> {
                 self.invoke(span, function, arguments)
             }
             Expression::Null(_) => Ok(Value::Null),
-            Expression::String { value, .. } => Ok(Value::String(value)),
-            Expression::Array { elements, .. } => Ok(Value::Array(
-                elements
+            Expression::String { value, .. } => Ok(Value::String(Rc::new(std::mem::take(value)))),
+            Expression::Array { elements, .. } => Ok(Value::Array(Rc::new(
+                std::mem::take(elements)
                     .into_iter()
                     .map(|e| self.eval_expression(e, None))
                     .collect::<Result<_>>()?,
-            )),
+            ))),
             Expression::Index {
                 collection, index, ..
             } => {
-                let collection = self.eval_expression(*collection, None)?;
-                let index = self.eval_expression(*index, None)?;
+                let collection = propagate_error!(self.eval_expression(collection.take(), None)?);
+                let index = propagate_error!(self.eval_expression(index.take(), None)?);
                 match (collection, index) {
                     (Value::Array(array), Value::Int(index)) => {
                         if index < 0 || index as usize >= array.len() {
-                            Err(Error {
+                            Err(Error::new(
                                 span,
-                                kind: ErrorKind::IndexOutOfBounds {
+                                ErrorKind::IndexOutOfBounds {
                                     len: array.len(),
                                     index,
                                 },
-                            })
+                            ))
                         } else {
                             Ok(array[index as usize].clone())
                         }
                     }
-                    (
-                        Value::Map(map),
-                        index @ Value::String(_) | index @ Value::Int(_) | index @ Value::Bool(_),
-                    ) => Ok(map.get(&index).cloned().unwrap_or(Value::Null)),
-                    (collection, index) => Err(Error {
+                    (Value::Bytes(bytes), Value::Int(index)) => {
+                        if index < 0 || index as usize >= bytes.len() {
+                            Err(Error::new(
+                                span,
+                                ErrorKind::IndexOutOfBounds {
+                                    len: bytes.len(),
+                                    index,
+                                },
+                            ))
+                        } else {
+                            Ok(Value::Int(bytes[index as usize].into()))
+                        }
+                    }
+                    (Value::Map(map), index) if index.is_hashable() => match map.get(&index) {
+                        Some(value) => Ok(value.clone()),
+                        None if self.strict => Err(Error::new(
+                            span,
+                            ErrorKind::MissingKey(format!("{index:?}")),
+                        )),
+                        None => Ok(Value::Null),
+                    },
+                    (Value::Map(_), index) => {
+                        Err(Error::new(span, ErrorKind::InvalidMapKey(index.into())))
+                    }
+                    (collection, index) => Err(Error::new(
                         span,
-                        kind: ErrorKind::InvalidIndex(collection.into(), index.into()),
-                    }),
+                        ErrorKind::InvalidIndex(collection.into(), index.into()),
+                    )),
                 }
             }
-            Expression::Map { elements, .. } => Ok(Value::Map(
-                elements
+            Expression::Map { elements, .. } => Ok(Value::Map(Rc::new(
+                std::mem::take(elements)
                     .into_iter()
                     .map(|(key, value)| {
                         let key_span = key.span();
                         let key = match self.eval_expression(key, None)? {
-                            key @ Value::String(_) | key @ Value::Int(_) | key @ Value::Bool(_) => {
-                                key
-                            }
+                            key if key.is_hashable() => key,
                             key => {
-                                return Err(Error {
-                                    span: key_span,
-                                    kind: ErrorKind::InvalidMapKey(key.into()),
-                                });
+                                return Err(Error::new(
+                                    key_span,
+                                    ErrorKind::InvalidMapKey(key.into()),
+                                ));
                             }
                         };
                         let value = self.eval_expression(value, None)?;
                         Ok((key, value))
                     })
                     .collect::<Result<_>>()?,
-            )),
+            ))),
+        }
+    }
+
+    /// Evaluates a prefix or infix expression. Operands can nest arbitrarily deeply on either
+    /// side, like in `1 + 2 + ... + n` or `-(1 + -(2 + ...))`, so this works through them with
+    /// stacks instead of recursing. Every other kind of operand is evaluated as usual.
+    fn eval_operators(&mut self, expression: Expression<'a>) -> Result<'a, Value<'a>> {
+        enum Work<'a> {
+            Eval(Expression<'a>),
+            /// Applies the operator to the operand on top of the value stack.
+            Prefix(Prefix),
+            /// Applies the operator to the two operands on top of the value stack.
+            Infix(InfixOperator),
+        }
+
+        let mut work = vec![Work::Eval(expression)];
+        // The values of evaluated operands, with their spans.
+        let mut values: Vec<(Span, Value<'a>)> = Vec::new();
+        while let Some(item) = work.pop() {
+            let (span, value) = match item {
+                Work::Eval(mut expression) => {
+                    match &mut expression {
+                        Expression::Prefix { prefix, right } => {
+                            work.push(Work::Prefix(prefix.clone()));
+                            work.push(Work::Eval(right.take()));
+                        }
+                        Expression::Infix {
+                            left,
+                            operator,
+                            right,
+                        } => {
+                            work.push(Work::Infix(*operator));
+                            work.push(Work::Eval(right.take()));
+                            work.push(Work::Eval(left.take()));
+                        }
+                        _ => {
+                            let span = expression.span();
+                            let value = propagate_error!(self.eval_expression(expression, None)?);
+                            values.push((span, value));
+                        }
+                    }
+                    continue;
+                }
+                Work::Prefix(prefix) => {
+                    let (right_span, right) = values.pop().expect("the operand was evaluated");
+                    let span = prefix.span.join(right_span);
+                    self.step(span)?;
+                    let value = match (prefix.operator, right) {
+                        (PrefixOperator::Neg, Value::Int(value)) => match value.checked_neg() {
+                            Some(value) => Value::Int(value),
+                            None => return Err(Error::new(span, ErrorKind::Overflow)),
+                        },
+                        (PrefixOperator::Not, right) => Value::Bool(!right.truthy()),
+                        (PrefixOperator::Neg, right) => {
+                            return Err(Error::new(span, ErrorKind::InvalidNeg(right.into())));
+                        }
+                    };
+                    (span, value)
+                }
+                Work::Infix(operator) => {
+                    let right = values.pop().expect("the right operand was evaluated");
+                    let left = values.pop().expect("the left operand was evaluated");
+                    let span = left.0.join(right.0);
+                    self.step(span)?;
+                    (span, Self::eval_infix(left, operator, right)?)
+                }
+            };
+            values.push((span, propagate_error!(value)));
+        }
+        let (_, value) = values.pop().expect("the expression was evaluated");
+        Ok(value)
+    }
+
+    /// Applies `operator` to two operands, each given with its span.
+    fn eval_infix(
+        (left_span, left): (Span, Value<'a>),
+        operator: InfixOperator,
+        (right_span, right): (Span, Value<'a>),
+    ) -> Result<'a, Value<'a>> {
+        let span = left_span.join(right_span);
+        let invalid = |left: Type, right: Type| {
+            Error::new(
+                span,
+                ErrorKind::InvalidInfix {
+                    operator,
+                    left: Operand {
+                        span: left_span,
+                        ty: left,
+                    },
+                    right: Operand {
+                        span: right_span,
+                        ty: right,
+                    },
+                },
+            )
+        };
+        match (left, operator, right) {
+            (left, InfixOperator::Eq, right) => Ok(Value::Bool(left == right)),
+            (left, InfixOperator::Neq, right) => Ok(Value::Bool(left != right)),
+            (Value::Int(l), _, Value::Int(r)) => {
+                let result = match operator {
+                    InfixOperator::Add => l.checked_add(r),
+                    InfixOperator::Sub => l.checked_sub(r),
+                    InfixOperator::Mul => l.checked_mul(r),
+                    InfixOperator::Div if r == 0 => {
+                        return Err(Error::new(span, ErrorKind::DivisionByZero));
+                    }
+                    InfixOperator::Div => l.checked_div(r),
+                    InfixOperator::LT => return Ok(Value::Bool(l < r)),
+                    InfixOperator::GT => return Ok(Value::Bool(l > r)),
+                    _ => return Err(invalid(Type::Int, Type::Int)),
+                };
+                result
+                    .map(Value::Int)
+                    .ok_or_else(|| Error::new(span, ErrorKind::Overflow))
+            }
+            (Value::String(mut l), InfixOperator::Add, Value::String(r)) => {
+                // Appends in place when nothing else holds on to the left operand.
+                Rc::make_mut(&mut l).push_str(&r);
+                Ok(Value::String(l))
+            }
+            (left, _, right) => Err(invalid(left.into(), right.into())),
         }
     }
 
-    fn invoke(
+    pub fn invoke(
         &mut self,
         call_span: Span,
         function: Rc<Function<'a>>,
         arguments: Vec<Value<'a>>,
     ) -> Result<'a, Value<'a>> {
         if arguments.len() != function.parameters.len() {
-            return Err(Error {
-                span: call_span,
-                kind: ErrorKind::WrongNumberOfArguments {
+            return Err(Error::new(
+                call_span,
+                ErrorKind::WrongNumberOfArguments {
                     expected: function.parameters.len(),
                     found: arguments.len(),
                 },
-            });
+            ));
         }
-        let mut inner = Environment::default();
-
-        inner.locals.extend(self.locals.clone());
-        inner
-            .locals
-            .extend(function.parameters.iter().cloned().zip(arguments));
+        if self.depth >= self.max_depth {
+            return Err(Error::new(
+                call_span,
+                ErrorKind::MaxDepthExceeded(self.max_depth),
+            ));
+        }
+        let _base = StackBase::enter();
+        let used = StackBase::used();
+        if used > self.max_stack {
+            return Err(Error::new(call_span, ErrorKind::StackExhausted(used)));
+        }
+        let mut locals: HashMap<_, _> =
+            function.parameters.iter().cloned().zip(arguments).collect();
         if let Some(name) = function.name.clone()
-            && !inner.locals.contains_key(&name)
+            && !locals.contains_key(&name)
         {
-            inner.locals.insert(name, Value::Function(function.clone()));
+            locals.insert(name, Value::Function(function.clone()));
+        }
+        let mut inner = Environment {
+            scope: Rc::new(RefCell::new(Scope {
+                locals,
+                parent: Some(function.scope.clone()),
+            })),
+            scopes: self.scopes.clone(),
+            args: self.args.clone(),
+            env_vars: self.env_vars.clone(),
+            depth: self.depth + 1,
+            max_depth: self.max_depth,
+            max_stack: self.max_stack,
+            steps_remaining: self.steps_remaining,
+            strict: self.strict,
+            stats: Stats {
+                peak_depth: self.stats.peak_depth.max(self.depth + 1),
+                ..self.stats
+            },
+            output: self.output.clone(),
+            interrupt: self.interrupt.clone(),
+            host_functions: self.host_functions.clone(),
+        };
+
+        let name = function
+            .name
+            .as_ref()
+            .map_or("<anonymous>", |name| name.name);
+        tracing::debug!(function = name, depth = inner.depth, "push frame");
+        let result = inner.eval_statements(function.body.statements.clone());
+        tracing::debug!(function = name, depth = inner.depth, "pop frame");
+        self.steps_remaining = inner.steps_remaining;
+        self.stats = inner.stats;
+        let result = match result {
+            Err(Error {
+                kind: ErrorKind::Return(value),
+                ..
+            }) => Ok(value),
+            result => result,
+        };
+        match result {
+            Ok(Value::Error(mut error)) => {
+                Rc::make_mut(&mut error).trace.push(call_span);
+                Ok(Value::Error(error))
+            }
+            Ok(value) => Ok(value),
+            Err(mut e) => {
+                e.trace.push(call_span);
+                Err(e)
+            }
         }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::parse_program;
+
+    fn eval(source: &str) -> Value<'_> {
+        Environment::default()
+            .eval(parse_program(source).unwrap())
+            .unwrap()
+    }
+
+    fn error(source: &str) -> Error<'_> {
+        Environment::default()
+            .eval(parse_program(source).unwrap())
+            .unwrap_err()
+    }
+
+    #[test]
+    fn long_sum_evaluates() {
+        let source = vec!["1"; 100_000].join(" + ");
+        assert_eq!(eval(&source), Value::Int(100_000));
+    }
+
+    #[test]
+    fn long_sum_evaluates_in_function() {
+        let source = format!(
+            "let f = fn() {{ {} }};\nf() + f()",
+            vec!["1"; 100_000].join(" + ")
+        );
+        assert_eq!(eval(&source), Value::Int(200_000));
+    }
+
+    #[test]
+    fn right_nested_operators_evaluate() {
+        let source = format!("0{}", " + -1".repeat(10_000));
+        assert_eq!(eval(&source), Value::Int(0));
+    }
+
+    #[test]
+    fn deeply_nested_groups_evaluate() {
+        let source = format!("{}1 + 1{}", "(".repeat(2_000), ")".repeat(2_000));
+        assert_eq!(eval(&source), Value::Int(2));
+    }
+
+    #[test]
+    fn arrays_are_map_keys() {
+        let source = r#"let m = {[1, "a"]: 1, [1, "b"]: 2}; m[[1, "b"]]"#;
+        assert_eq!(eval(source), Value::Int(2));
+    }
+
+    #[test]
+    fn arrays_of_functions_are_not_map_keys() {
+        let program = parse_program("{[fn() {}]: 1}").unwrap();
+        let error = Environment::default().eval(program).unwrap_err();
+        assert!(matches!(error.kind, ErrorKind::InvalidMapKey(Type::Array)));
+    }
+
+    fn report(source: &str) -> String {
+        crate::report::set_color(crate::report::ColorChoice::Never);
+        let program = parse_program(source).unwrap();
+        let error = Environment::default().eval(program).unwrap_err();
+        let mut out = Vec::new();
+        let source = Source {
+            name: "test.mk",
+            text: source,
+        };
+        error.write_report(source, &mut out);
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn recursive_calls_share_a_trace_label() {
+        let report = report("let f = fn(n) { if (n == 0) { 1 / 0 } else { f(n - 1) } };\nf(20)");
+        assert!(report.contains("1: called here (×20)"));
+        assert!(report.contains("2: called here"));
+        assert!(!report.contains("more calls"));
+    }
+
+    #[test]
+    fn long_traces_are_cut_short() {
+        let report = report(
+            "let a = fn(n) { if (n == 0) { 1 / 0 } else { b(n - 1) } };\n\
+             let b = fn(n) { a(n) };\n\
+             a(20)",
+        );
+        assert!(report.contains(&format!("{MAX_TRACE_FRAMES}: called here")));
+        assert!(!report.contains(&format!("{}: called here", MAX_TRACE_FRAMES + 1)));
+        assert!(report.contains("… 33 more calls"));
+    }
+
+    #[test]
+    fn scopes_are_freed_with_their_environment() {
+        let mut env = Environment::default();
+        let program = parse_program(
+            "let count = fn(n) { if (n > 0) { count(n - 1) } else { fn() { n } } };
+            let f = count(3);
+            f()",
+        )
+        .unwrap();
+        assert!(matches!(env.eval(program), Ok(Value::Int(0))));
+        let global = Rc::downgrade(&env.scope);
+        let Some(Value::Function(f)) = env.get("f") else {
+            panic!("f is a function");
+        };
+        let call = Rc::downgrade(&f.scope);
+        drop(f);
+        drop(env);
+        assert_eq!(global.strong_count(), 0);
+        assert_eq!(call.strong_count(), 0);
+    }
+
+    #[test]
+    fn division_by_zero_is_an_error() {
+        let error = error("let x = 0;\n1 / x");
+        assert!(matches!(error.kind, ErrorKind::DivisionByZero));
+        assert_eq!(error.span, (11..16).into());
+    }
+
+    #[test]
+    fn division_rounds_toward_zero() {
+        assert_eq!(eval("7 / 2"), Value::Int(3));
+        assert_eq!(eval("(0 - 7) / 2"), Value::Int(-3));
+    }
+
+    #[test]
+    fn overflow_is_an_error() {
+        let min = "(0 - 9223372036854775807 - 1)";
+        assert_eq!(eval(&format!("{min} + 1")), Value::Int(i64::MIN + 1));
+        for source in [
+            "9223372036854775807 + 1".to_string(),
+            format!("{min} - 1"),
+            "4611686018427387904 * 2".to_string(),
+            format!("{min} / (0 - 1)"),
+            format!("-{min}"),
+        ] {
+            assert!(
+                matches!(error(&source).kind, ErrorKind::Overflow),
+                "{source} didn't overflow"
+            );
+        }
+    }
+
+    #[test]
+    fn strict_mode_makes_missing_keys_errors() {
+        assert_eq!(eval(r#"{"a": 1}["b"]"#), Value::Null);
+        let error = error("// strict\n{\"a\": 1}[\"b\"]");
+        assert!(matches!(error.kind, ErrorKind::MissingKey(key) if key == r#""b""#));
+
+        let mut env = Environment {
+            strict: true,
+            ..Default::default()
+        };
+        let program = parse_program(r#"{"a": 1}["b"]"#).unwrap();
+        assert!(matches!(
+            env.eval(program).unwrap_err().kind,
+            ErrorKind::MissingKey(_)
+        ));
+    }
+
+    #[test]
+    fn errors_are_values() {
+        assert_eq!(
+            eval(r#"let e = error("bad"); [is_error(e), is_error(1)]"#),
+            Value::from(vec![true, false])
+        );
+        // Using an error in an operation passes it on.
+        for source in [
+            r#"error("bad")"#,
+            r#"error("bad") == 1"#,
+            r#"1 + error("bad")"#,
+        ] {
+            assert!(
+                matches!(eval(source), Value::Error(e) if e.message == "bad"),
+                "{source}"
+            );
+        }
+    }
+
+    #[test]
+    fn error_values_know_where_they_came_from() {
+        let source = "let f = fn() { error(\"bad\") };\nf()";
+        let Value::Error(error) = eval(source) else {
+            panic!("an error value");
+        };
+        assert_eq!(error.span, (15..27).into());
+        assert_eq!(error.trace, [Span::from(31..34)]);
+    }
+
+    #[test]
+    fn functions_are_equal_only_to_themselves() {
+        assert_eq!(
+            eval("let f = fn(x) { x }; let g = f; [f == g, f == fn(x) { x }, f != g]"),
+            Value::from(vec![true, false, false])
+        );
+    }
+
+    #[test]
+    fn bindings_shadow_host_functions_and_intrinsics() {
+        assert_eq!(eval("let len = fn(x) { 42 }; len([1])"), Value::Int(42));
+
+        let mut env = Environment::default();
+        env.register("double", |args| match args {
+            [Value::Int(n)] => Ok(Value::Int(n * 2)),
+            _ => Err("double takes one integer".into()),
+        });
+        env.register("len", |_| Ok(Value::Int(7)));
+        let program = parse_program("[double(2), len([1])]").unwrap();
+        assert_eq!(env.eval(program).unwrap(), Value::from(vec![4, 7]));
+        let program = parse_program("let double = fn(x) { x }; double(2)").unwrap();
+        assert_eq!(env.eval(program).unwrap(), Value::Int(2));
+    }
+
+    #[test]
+    fn return_leaves_only_its_own_function() {
+        let source = "let f = fn() {\n  if (true) { return 1; }\n  2\n};\n\
+                      let g = fn() { f(); 3 };\n\
+                      [f(), g()]";
+        assert_eq!(eval(source), Value::from(vec![1, 3]));
+        assert_eq!(eval("return 5; 6"), Value::Int(5));
+        assert_eq!(eval("if (true) { 1; 2 }"), Value::Int(2));
+        assert_eq!(eval("if (true) { 1; }"), Value::Null);
+    }
+
+    #[test]
+    fn unknown_names_suggest_similar_ones() {
+        let suggestion = |source| match error(source).kind {
+            ErrorKind::UnknownIdentifier { suggestion, .. } => suggestion,
+            kind => panic!("{source} failed with {kind}"),
+        };
+        assert_eq!(suggestion("let value = 1; valeu").as_deref(), Some("value"));
+        assert_eq!(suggestion("let value = 1; xyz"), None);
+        assert_eq!(suggestion("prnt(1)").as_deref(), Some("print"));
+        assert_eq!(
+            suggestion("let ab = 1; let ac = 2; ax").as_deref(),
+            Some("ab")
+        );
+    }
+
+    #[test]
+    fn operator_errors_label_both_operands() {
+        let report = report("let s = \"a\";\n1 + s");
+        assert!(report.contains("cannot use + on Int and String"));
+        assert!(report.contains("this is Int"));
+        assert!(report.contains("this is String"));
+    }
+
+    #[test]
+    fn call_depth_is_limited() {
+        let source = "let f = fn(n) { if (n == 0) { 0 } else { f(n - 1) } };";
+        let mut env = Environment {
+            max_depth: 10,
+            ..Default::default()
+        };
+        let program = parse_program(source).unwrap();
+        env.eval(program).unwrap();
+        let program = parse_program("f(9)").unwrap();
+        assert_eq!(env.eval(program).unwrap(), Value::Int(0));
+        let program = parse_program("f(10)").unwrap();
+        assert!(matches!(
+            env.eval(program).unwrap_err().kind,
+            ErrorKind::MaxDepthExceeded(10)
+        ));
+    }
+
+    #[test]
+    fn recursion_stops_before_the_stack_overflows() {
+        let source = "let f = fn(n) { if (n == 0) { 0 } else { 1 + f(n - 1) } };";
+        let mut env = Environment::default();
+        let program = parse_program(source).unwrap();
+        env.eval(program).unwrap();
+        let program = parse_program("f(20)").unwrap();
+        assert_eq!(env.eval(program).unwrap(), Value::Int(20));
+        // Far deeper than the stack of a test thread allows, even in a release build.
+        let program = parse_program("f(1000000)").unwrap();
+        assert!(matches!(
+            env.eval(program).unwrap_err().kind,
+            ErrorKind::StackExhausted(_)
+        ));
+        // The stack is measured from where each evaluation starts.
+        let program = parse_program("f(20)").unwrap();
+        assert_eq!(env.eval(program).unwrap(), Value::Int(20));
+    }
+
+    #[test]
+    fn steps_are_limited() {
+        let source = "let f = fn(n) { if (n == 0) { 0 } else { f(n - 1) } };\nf(20)";
+        let run = |steps| {
+            let mut env = Environment {
+                steps_remaining: Some(steps),
+                ..Default::default()
+            };
+            env.eval(parse_program(source).unwrap())
+        };
+        assert!(run(10_000).is_ok());
+        assert!(matches!(
+            run(100).unwrap_err().kind,
+            ErrorKind::StepLimitExceeded
+        ));
+    }
 
-        inner.eval_statements(function.body.statements.clone())
+    #[test]
+    fn changed_collections_are_copies() {
+        assert_eq!(
+            eval("let a = [1]; let b = push(a, 2); let c = push(a, 3); [a, b, c]"),
+            Value::from(vec![vec![1], vec![1, 2], vec![1, 3]])
+        );
     }
 }
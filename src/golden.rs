@@ -0,0 +1,128 @@
+//! Golden-file tests. Each `.mk` file in a directory is run, and everything it prints, including
+//! warnings and errors, is compared with the `.out` file next to it.
+
+use monkey::{
+    eval::{Environment, Error, ErrorKind},
+    lint,
+    parse::parse_program,
+    report::{self, ColorChoice, Source},
+    sync::{Rc, RefCell},
+    value::Value,
+};
+use std::{io::Write, path::Path};
+
+/// Runs every example in `dir`, printing which ones fail and how. With `bless`, each example's
+/// output is written to its `.out` file instead. Returns whether all of them passed.
+pub fn run(dir: &Path, max_depth: usize, max_stack: usize, bless: bool) -> std::io::Result<bool> {
+    // Reports are compared as plain text.
+    report::set_color(ColorChoice::Never);
+
+    let mut examples = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "mk") {
+            examples.push(path);
+        }
+    }
+    examples.sort();
+
+    let mut failed = 0;
+    for path in &examples {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let text = std::fs::read_to_string(path)?;
+        let output = output(&name, &text, max_depth, max_stack);
+        let expected_path = path.with_extension("out");
+        if bless {
+            std::fs::write(&expected_path, &output)?;
+            continue;
+        }
+        match std::fs::read_to_string(&expected_path) {
+            Ok(expected) if expected == output => println!("ok      {name}"),
+            Ok(expected) => {
+                failed += 1;
+                println!("FAILED  {name}");
+                print_diff(&expected, &output);
+            }
+            Err(err) => {
+                failed += 1;
+                println!(
+                    "FAILED  {name}: couldn't read {}: {err}",
+                    expected_path.display()
+                );
+            }
+        }
+    }
+
+    if bless {
+        println!("wrote the output of {} examples", examples.len());
+    } else {
+        println!(
+            "{} passed, {failed} failed (evaluator)",
+            examples.len() - failed
+        );
+    }
+    Ok(failed == 0)
+}
+
+/// Runs a program the way the CLI does, returning everything it would print.
+fn output(name: &str, text: &str, max_depth: usize, max_stack: usize) -> String {
+    let source = Source { name, text };
+    let output = Rc::new(RefCell::new(Vec::new()));
+    let program = match parse_program(text) {
+        Ok(program) => program,
+        Err(errors) => {
+            let mut out = output.borrow_mut();
+            for error in errors {
+                error.write_report(source, &mut *out);
+            }
+            return String::from_utf8_lossy(&out).into_owned();
+        }
+    };
+    for warning in lint::check(&program) {
+        warning.write_report(source, program.strict, &mut *output.borrow_mut());
+    }
+
+    let mut env = Environment {
+        max_depth,
+        max_stack,
+        strict: program.strict,
+        output: Some(output.clone()),
+        ..Default::default()
+    };
+    let result = env.eval(program);
+    let mut out = output.borrow_mut();
+    match result {
+        Err(Error {
+            kind: ErrorKind::Exit(code),
+            ..
+        }) => {
+            let _ = writeln!(out, "exited with status {code}");
+        }
+        Err(e) => e.write_report(source, &mut *out),
+        Ok(Value::Error(error)) => {
+            Error::from(Rc::unwrap_or_clone(error)).write_report(source, &mut *out)
+        }
+        Ok(Value::Null) => {}
+        Ok(value) => {
+            let _ = writeln!(out, "{value}");
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Prints the lines from the first one that differs, as they were expected and as they are.
+fn print_diff(expected: &str, actual: &str) {
+    let same = expected
+        .lines()
+        .zip(actual.lines())
+        .take_while(|(expected, actual)| expected == actual)
+        .count();
+    println!("  expected, from line {}:", same + 1);
+    for line in expected.lines().skip(same) {
+        println!("  - {line}");
+    }
+    println!("  but got:");
+    for line in actual.lines().skip(same) {
+        println!("  + {line}");
+    }
+}
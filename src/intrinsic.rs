@@ -1,36 +1,1206 @@
+//! The built-in functions available to every program, such as `len` and `println`.
+
 use crate::{
     ast::Span,
-    eval::{Error, ErrorKind, Result},
-    value::Value,
+    eval::{Environment, Error, ErrorKind, Result},
+    sync::{MaybeSync, Rc},
+    value::{ErrorValue, Function, Type, Value},
 };
+use std::collections::HashMap;
+
+pub type Intrinsic =
+    for<'a> fn(&mut Environment<'a>, Span, Vec<Argument<'a>>) -> Result<'a, Value<'a>>;
+
+/// A function that the program embedding the interpreter provides, registered with
+/// [`Environment::register`]. It's called like an intrinsic, with the values of its arguments.
+pub trait HostFunction<'a>: Fn(&[Value<'a>]) -> HostResult<'a> + MaybeSync {}
+
+impl<'a, F: Fn(&[Value<'a>]) -> HostResult<'a> + MaybeSync> HostFunction<'a> for F {}
+
+/// What a [`HostFunction`] returns. An error stops evaluation, and is reported at the call.
+pub type HostResult<'a> = std::result::Result<Value<'a>, Box<dyn std::error::Error + Send + Sync>>;
+
+pub struct Argument<'a> {
+    pub span: Span,
+    pub value: Value<'a>,
+}
 
-pub type Intrinsic<'a> = fn(Span, Vec<Value<'a>>) -> Result<'a, Value<'a>>;
+impl<'a> Argument<'a> {
+    fn wrong_type(span: Span, expected: Type, found: Value<'a>) -> Error<'a> {
+        Error::new(
+            span,
+            ErrorKind::WrongArgumentType {
+                expected,
+                found: found.into(),
+            },
+        )
+    }
+
+    fn into_int(self) -> Result<'a, i64> {
+        match self.value {
+            Value::Int(i) => Ok(i),
+            value => Err(Self::wrong_type(self.span, Type::Int, value)),
+        }
+    }
+
+    fn into_string(self) -> Result<'a, String> {
+        match self.value {
+            Value::String(s) => Ok(Rc::unwrap_or_clone(s)),
+            value => Err(Self::wrong_type(self.span, Type::String, value)),
+        }
+    }
 
-pub fn find_intrinsic(name: &str) -> Option<Intrinsic<'_>> {
-    match name {
-        "print" => Some(print),
-        "dbg" => Some(dbg),
-        _ => None,
+    fn into_bytes(self) -> Result<'a, Rc<Vec<u8>>> {
+        match self.value {
+            Value::Bytes(b) => Ok(b),
+            value => Err(Self::wrong_type(self.span, Type::Bytes, value)),
+        }
+    }
+
+    fn into_function(self) -> Result<'a, Rc<Function<'a>>> {
+        match self.value {
+            Value::Function(f) => Ok(f),
+            value => Err(Self::wrong_type(self.span, Type::Function, value)),
+        }
+    }
+
+    #[cfg(feature = "regex")]
+    fn into_regex(self) -> Result<'a, regex::Regex> {
+        let span = self.span;
+        regex::Regex::new(&self.into_string()?)
+            .map_err(|e| Error::new(span, ErrorKind::InvalidRegex(e)))
+    }
+
+    fn into_array(self) -> Result<'a, Rc<Vec<Value<'a>>>> {
+        match self.value {
+            Value::Array(a) => Ok(a),
+            value => Err(Self::wrong_type(self.span, Type::Array, value)),
+        }
     }
 }
 
-fn print<'a>(_call_span: Span, args: Vec<Value<'a>>) -> Result<'a, Value<'a>> {
-    for arg in args {
-        println!("{arg}");
+fn expect_args<'a, const N: usize>(
+    call_span: Span,
+    args: Vec<Argument<'a>>,
+) -> Result<'a, [Argument<'a>; N]> {
+    let found = args.len();
+    args.try_into().map_err(|_| {
+        Error::new(
+            call_span,
+            ErrorKind::WrongNumberOfArguments { expected: N, found },
+        )
+    })
+}
+
+/// Converts `index` into a position in an array of length `len`, allowing `len` itself only if
+/// `allow_end` is set.
+fn array_position<'a>(span: Span, len: usize, index: i64, allow_end: bool) -> Result<'a, usize> {
+    let end = if allow_end { len + 1 } else { len };
+    if index < 0 || index as usize >= end {
+        Err(Error::new(span, ErrorKind::IndexOutOfBounds { len, index }))
+    } else {
+        Ok(index as usize)
     }
+}
+
+fn io_error<'a>(span: Span) -> impl Fn(std::io::Error) -> Error<'a> {
+    move |e| Error::new(span, ErrorKind::Io(e))
+}
+
+/// Every intrinsic, by the name scripts call it by.
+pub const INTRINSICS: &[(&str, Intrinsic)] = &[
+    ("print", print),
+    ("println", println),
+    ("eprint", eprint),
+    ("eprintln", eprintln),
+    ("dbg", dbg),
+    ("pprint", pprint),
+    ("push", push),
+    ("pop", pop),
+    ("insert", insert),
+    ("remove", remove),
+    ("range", range),
+    ("take", take),
+    ("drop", drop),
+    ("chunk", chunk),
+    ("windows", windows),
+    ("find", find),
+    ("any", any),
+    ("all", all),
+    ("input", input),
+    ("args", args),
+    ("env", env),
+    ("set_env", set_env),
+    ("time", time),
+    ("sleep", sleep),
+    ("exit", exit),
+    ("error", error),
+    ("is_error", is_error),
+    ("clone", clone),
+    ("exec", exec),
+    ("pad_left", pad_left),
+    ("pad_right", pad_right),
+    ("lines", lines),
+    ("len", len),
+    ("bytes", bytes),
+    ("slice", slice),
+    ("read_file_bytes", read_file_bytes),
+    ("write_file_bytes", write_file_bytes),
+    #[cfg(feature = "http")]
+    ("http_get", http_get),
+    #[cfg(feature = "json")]
+    ("parse_json", parse_json),
+    #[cfg(feature = "json")]
+    ("to_json", to_json),
+    #[cfg(feature = "regex")]
+    ("regex_match", regex_match),
+    #[cfg(feature = "regex")]
+    ("regex_find_all", regex_find_all),
+    #[cfg(feature = "regex")]
+    ("regex_replace", regex_replace),
+];
+
+pub fn find_intrinsic(name: &str) -> Option<Intrinsic> {
+    INTRINSICS
+        .iter()
+        .find(|(intrinsic, _)| *intrinsic == name)
+        .map(|&(_, intrinsic)| intrinsic)
+}
+
+fn write_args(
+    mut out: impl std::io::Write,
+    args: Vec<Argument>,
+    newline: bool,
+) -> std::io::Result<()> {
+    for (i, arg) in args.into_iter().enumerate() {
+        if i > 0 {
+            write!(out, " ")?;
+        }
+        write!(out, "{}", arg.value)?;
+    }
+    if newline {
+        writeln!(out)?;
+    }
+    out.flush()
+}
+
+/// Runs `write` on the environment's output, or on stdout if it doesn't have one.
+fn write_output<T>(env: &Environment, write: impl FnOnce(&mut dyn std::io::Write) -> T) -> T {
+    match &env.output {
+        Some(output) => write(&mut *output.borrow_mut()),
+        None => write(&mut std::io::stdout().lock()),
+    }
+}
+
+fn print<'a>(
+    env: &mut Environment<'a>,
+    call_span: Span,
+    args: Vec<Argument<'a>>,
+) -> Result<'a, Value<'a>> {
+    write_output(env, |out| write_args(out, args, false)).map_err(io_error(call_span))?;
     Ok(Value::Null)
 }
 
-fn dbg<'a>(call_span: Span, args: Vec<Value<'a>>) -> Result<'a, Value<'a>> {
-    if args.len() != 1 {
-        return Err(Error {
-            span: call_span,
-            kind: ErrorKind::WrongNumberOfArguments {
-                expected: 1,
-                found: args.len(),
-            },
-        });
+fn println<'a>(
+    env: &mut Environment<'a>,
+    call_span: Span,
+    args: Vec<Argument<'a>>,
+) -> Result<'a, Value<'a>> {
+    write_output(env, |out| write_args(out, args, true)).map_err(io_error(call_span))?;
+    Ok(Value::Null)
+}
+
+fn eprint<'a>(
+    _env: &mut Environment<'a>,
+    call_span: Span,
+    args: Vec<Argument<'a>>,
+) -> Result<'a, Value<'a>> {
+    write_args(std::io::stderr().lock(), args, false).map_err(io_error(call_span))?;
+    Ok(Value::Null)
+}
+
+fn eprintln<'a>(
+    _env: &mut Environment<'a>,
+    call_span: Span,
+    args: Vec<Argument<'a>>,
+) -> Result<'a, Value<'a>> {
+    write_args(std::io::stderr().lock(), args, true).map_err(io_error(call_span))?;
+    Ok(Value::Null)
+}
+
+fn dbg<'a>(
+    env: &mut Environment<'a>,
+    call_span: Span,
+    args: Vec<Argument<'a>>,
+) -> Result<'a, Value<'a>> {
+    let [arg] = expect_args(call_span, args)?;
+    write_output(env, |out| writeln!(out, "{:?}", arg.value)).map_err(io_error(call_span))?;
+    Ok(arg.value)
+}
+
+fn pprint<'a>(
+    env: &mut Environment<'a>,
+    call_span: Span,
+    args: Vec<Argument<'a>>,
+) -> Result<'a, Value<'a>> {
+    let [arg] = expect_args(call_span, args)?;
+    write_output(env, |out| writeln!(out, "{:#?}", arg.value)).map_err(io_error(call_span))?;
+    Ok(Value::Null)
+}
+
+fn push<'a>(
+    _env: &mut Environment<'a>,
+    call_span: Span,
+    args: Vec<Argument<'a>>,
+) -> Result<'a, Value<'a>> {
+    let [array, value] = expect_args(call_span, args)?;
+    let mut array = array.into_array()?;
+    Rc::make_mut(&mut array).push(value.value);
+    Ok(Value::Array(array))
+}
+
+fn pop<'a>(
+    _env: &mut Environment<'a>,
+    call_span: Span,
+    args: Vec<Argument<'a>>,
+) -> Result<'a, Value<'a>> {
+    let [array] = expect_args(call_span, args)?;
+    let span = array.span;
+    let mut array = array.into_array()?;
+    if Rc::make_mut(&mut array).pop().is_none() {
+        return Err(Error::new(
+            span,
+            ErrorKind::IndexOutOfBounds { len: 0, index: 0 },
+        ));
+    }
+    Ok(Value::Array(array))
+}
+
+fn insert<'a>(
+    _env: &mut Environment<'a>,
+    call_span: Span,
+    args: Vec<Argument<'a>>,
+) -> Result<'a, Value<'a>> {
+    let [array, index, value] = expect_args(call_span, args)?;
+    let index_span = index.span;
+    let mut array = array.into_array()?;
+    let index = array_position(index_span, array.len(), index.into_int()?, true)?;
+    Rc::make_mut(&mut array).insert(index, value.value);
+    Ok(Value::Array(array))
+}
+
+fn remove<'a>(
+    _env: &mut Environment<'a>,
+    call_span: Span,
+    args: Vec<Argument<'a>>,
+) -> Result<'a, Value<'a>> {
+    let [array, index] = expect_args(call_span, args)?;
+    let index_span = index.span;
+    let mut array = array.into_array()?;
+    let index = array_position(index_span, array.len(), index.into_int()?, false)?;
+    Rc::make_mut(&mut array).remove(index);
+    Ok(Value::Array(array))
+}
+
+fn range<'a>(
+    _env: &mut Environment<'a>,
+    call_span: Span,
+    args: Vec<Argument<'a>>,
+) -> Result<'a, Value<'a>> {
+    let (start, end, step) = match args.len() {
+        1 => {
+            let [end] = expect_args(call_span, args)?;
+            (0, end.into_int()?, 1)
+        }
+        2 => {
+            let [start, end] = expect_args(call_span, args)?;
+            (start.into_int()?, end.into_int()?, 1)
+        }
+        _ => {
+            let [start, end, step] = expect_args(call_span, args)?;
+            let step_span = step.span;
+            let step = step.into_int()?;
+            if step == 0 {
+                return Err(Error::new(step_span, ErrorKind::ZeroStep));
+            }
+            (start.into_int()?, end.into_int()?, step)
+        }
+    };
+
+    // A stride too big for `usize` can only reach the first value, as `usize::MAX` does.
+    let stride = usize::try_from(step.unsigned_abs()).unwrap_or(usize::MAX);
+    let values: Vec<_> = if step > 0 {
+        (start..end).step_by(stride).map(Value::Int).collect()
+    } else if start > end {
+        ((end + 1)..=start)
+            .rev()
+            .step_by(stride)
+            .map(Value::Int)
+            .collect()
+    } else {
+        Vec::new()
+    };
+    Ok(Value::Array(Rc::new(values)))
+}
+
+fn take<'a>(
+    _env: &mut Environment<'a>,
+    call_span: Span,
+    args: Vec<Argument<'a>>,
+) -> Result<'a, Value<'a>> {
+    let [array, count] = expect_args(call_span, args)?;
+    let count_span = count.span;
+    let mut array = array.into_array()?;
+    let count = array_position(count_span, array.len(), count.into_int()?, true)?;
+    Rc::make_mut(&mut array).truncate(count);
+    Ok(Value::Array(array))
+}
+
+fn drop<'a>(
+    _env: &mut Environment<'a>,
+    call_span: Span,
+    args: Vec<Argument<'a>>,
+) -> Result<'a, Value<'a>> {
+    let [array, count] = expect_args(call_span, args)?;
+    let count_span = count.span;
+    let mut array = array.into_array()?;
+    let count = array_position(count_span, array.len(), count.into_int()?, true)?;
+    Rc::make_mut(&mut array).drain(..count);
+    Ok(Value::Array(array))
+}
+
+/// Unpacks the `(array, size)` arguments shared by `chunk` and `windows`.
+fn array_and_size<'a>(
+    call_span: Span,
+    args: Vec<Argument<'a>>,
+) -> Result<'a, (Rc<Vec<Value<'a>>>, usize)> {
+    let [array, size] = expect_args(call_span, args)?;
+    let size_span = size.span;
+    let array = array.into_array()?;
+    match size.into_int()? {
+        size @ 1.. => Ok((array, size as usize)),
+        size => Err(Error::new(size_span, ErrorKind::NonPositiveSize(size))),
+    }
+}
+
+fn chunk<'a>(
+    _env: &mut Environment<'a>,
+    call_span: Span,
+    args: Vec<Argument<'a>>,
+) -> Result<'a, Value<'a>> {
+    let (array, size) = array_and_size(call_span, args)?;
+    Ok(Value::Array(Rc::new(
+        array
+            .chunks(size)
+            .map(|chunk| Value::Array(Rc::new(chunk.to_vec())))
+            .collect(),
+    )))
+}
+
+fn windows<'a>(
+    _env: &mut Environment<'a>,
+    call_span: Span,
+    args: Vec<Argument<'a>>,
+) -> Result<'a, Value<'a>> {
+    let (array, size) = array_and_size(call_span, args)?;
+    Ok(Value::Array(Rc::new(
+        array
+            .windows(size)
+            .map(|window| Value::Array(Rc::new(window.to_vec())))
+            .collect(),
+    )))
+}
+
+/// Unpacks the `(array, predicate)` arguments shared by the predicate intrinsics.
+fn array_and_predicate<'a>(
+    call_span: Span,
+    args: Vec<Argument<'a>>,
+) -> Result<'a, (Rc<Vec<Value<'a>>>, Rc<Function<'a>>)> {
+    let [array, predicate] = expect_args(call_span, args)?;
+    Ok((array.into_array()?, predicate.into_function()?))
+}
+
+fn find<'a>(
+    env: &mut Environment<'a>,
+    call_span: Span,
+    args: Vec<Argument<'a>>,
+) -> Result<'a, Value<'a>> {
+    let (array, predicate) = array_and_predicate(call_span, args)?;
+    for element in array.iter() {
+        if env
+            .invoke(call_span, predicate.clone(), vec![element.clone()])?
+            .truthy()
+        {
+            return Ok(element.clone());
+        }
+    }
+    Ok(Value::Null)
+}
+
+fn any<'a>(
+    env: &mut Environment<'a>,
+    call_span: Span,
+    args: Vec<Argument<'a>>,
+) -> Result<'a, Value<'a>> {
+    let (array, predicate) = array_and_predicate(call_span, args)?;
+    for element in array.iter() {
+        if env
+            .invoke(call_span, predicate.clone(), vec![element.clone()])?
+            .truthy()
+        {
+            return Ok(Value::Bool(true));
+        }
+    }
+    Ok(Value::Bool(false))
+}
+
+fn all<'a>(
+    env: &mut Environment<'a>,
+    call_span: Span,
+    args: Vec<Argument<'a>>,
+) -> Result<'a, Value<'a>> {
+    let (array, predicate) = array_and_predicate(call_span, args)?;
+    for element in array.iter() {
+        if !env
+            .invoke(call_span, predicate.clone(), vec![element.clone()])?
+            .truthy()
+        {
+            return Ok(Value::Bool(false));
+        }
+    }
+    Ok(Value::Bool(true))
+}
+
+fn input<'a>(
+    env: &mut Environment<'a>,
+    call_span: Span,
+    args: Vec<Argument<'a>>,
+) -> Result<'a, Value<'a>> {
+    if !args.is_empty() {
+        let [prompt] = expect_args(call_span, args)?;
+        let prompt = prompt.into_string()?;
+        write_output(env, |out| {
+            write!(out, "{prompt}")?;
+            out.flush()
+        })
+        .map_err(io_error(call_span))?;
+    }
+
+    let mut line = String::new();
+    if std::io::stdin()
+        .read_line(&mut line)
+        .map_err(io_error(call_span))?
+        == 0
+    {
+        return Ok(Value::Null);
+    }
+    if line.ends_with('\n') {
+        line.pop();
+        if line.ends_with('\r') {
+            line.pop();
+        }
+    }
+    Ok(Value::String(Rc::new(line)))
+}
+
+fn args<'a>(
+    env: &mut Environment<'a>,
+    call_span: Span,
+    args: Vec<Argument<'a>>,
+) -> Result<'a, Value<'a>> {
+    let [] = expect_args(call_span, args)?;
+    Ok(Value::Array(Rc::new(
+        env.args
+            .iter()
+            .map(|arg| Value::String(Rc::new(arg.clone())))
+            .collect(),
+    )))
+}
+
+fn env<'a>(
+    env: &mut Environment<'a>,
+    call_span: Span,
+    args: Vec<Argument<'a>>,
+) -> Result<'a, Value<'a>> {
+    let [name] = expect_args(call_span, args)?;
+    let name = name.into_string()?;
+    let value = env.env_vars.borrow().get(&name).cloned();
+    Ok(value
+        .or_else(|| std::env::var(&name).ok())
+        .map(|value| Value::String(Rc::new(value)))
+        .unwrap_or(Value::Null))
+}
+
+fn set_env<'a>(
+    env: &mut Environment<'a>,
+    call_span: Span,
+    args: Vec<Argument<'a>>,
+) -> Result<'a, Value<'a>> {
+    let [name, value] = expect_args(call_span, args)?;
+    let (name_span, value_span) = (name.span, value.span);
+    let (name, value) = (name.into_string()?, value.into_string()?);
+    if name.is_empty() || name.contains(['=', '\0']) {
+        return Err(Error::new(name_span, ErrorKind::InvalidEnvVar(name)));
+    }
+    if value.contains('\0') {
+        return Err(Error::new(value_span, ErrorKind::InvalidEnvVar(value)));
+    }
+    env.env_vars.borrow_mut().insert(name, value);
+    Ok(Value::Null)
+}
+
+fn time<'a>(
+    _env: &mut Environment<'a>,
+    call_span: Span,
+    args: Vec<Argument<'a>>,
+) -> Result<'a, Value<'a>> {
+    let [] = expect_args(call_span, args)?;
+    // There's no system clock in the browser, only JavaScript's.
+    #[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+    return Ok(Value::Int(js_sys::Date::now() as i64));
+    #[cfg(not(all(feature = "wasm", target_arch = "wasm32")))]
+    {
+        let elapsed = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default();
+        Ok(Value::Int(elapsed.as_millis() as i64))
+    }
+}
+
+fn sleep<'a>(
+    _env: &mut Environment<'a>,
+    call_span: Span,
+    args: Vec<Argument<'a>>,
+) -> Result<'a, Value<'a>> {
+    let [millis] = expect_args(call_span, args)?;
+    let span = millis.span;
+    let millis = millis.into_int()?;
+    if millis < 0 {
+        return Err(Error::new(span, ErrorKind::NegativeDuration(millis)));
+    }
+    // Sleeping panics where there are no threads.
+    if cfg!(all(target_arch = "wasm32", target_os = "unknown")) {
+        return Err(io_error(call_span)(std::io::ErrorKind::Unsupported.into()));
+    }
+    std::thread::sleep(std::time::Duration::from_millis(millis as u64));
+    Ok(Value::Null)
+}
+
+fn exit<'a>(
+    _env: &mut Environment<'a>,
+    call_span: Span,
+    args: Vec<Argument<'a>>,
+) -> Result<'a, Value<'a>> {
+    let code = if args.is_empty() {
+        0
+    } else {
+        let [code] = expect_args(call_span, args)?;
+        let code_span = code.span;
+        let code = code.into_int()?;
+        i32::try_from(code).map_err(|_| Error::new(code_span, ErrorKind::InvalidExitCode(code)))?
+    };
+    Err(Error::new(call_span, ErrorKind::Exit(code)))
+}
+
+fn error<'a>(
+    _env: &mut Environment<'a>,
+    call_span: Span,
+    args: Vec<Argument<'a>>,
+) -> Result<'a, Value<'a>> {
+    let [message] = expect_args(call_span, args)?;
+    Ok(Value::Error(Rc::new(ErrorValue {
+        message: message.into_string()?,
+        span: call_span,
+        trace: Vec::new(),
+    })))
+}
+
+fn is_error<'a>(
+    _env: &mut Environment<'a>,
+    call_span: Span,
+    args: Vec<Argument<'a>>,
+) -> Result<'a, Value<'a>> {
+    let [value] = expect_args(call_span, args)?;
+    Ok(Value::Bool(matches!(value.value, Value::Error(_))))
+}
+
+fn clone<'a>(
+    _env: &mut Environment<'a>,
+    call_span: Span,
+    args: Vec<Argument<'a>>,
+) -> Result<'a, Value<'a>> {
+    let [value] = expect_args(call_span, args)?;
+    Ok(value.value.deep_clone())
+}
+
+fn exec<'a>(
+    env: &mut Environment<'a>,
+    call_span: Span,
+    args: Vec<Argument<'a>>,
+) -> Result<'a, Value<'a>> {
+    let (program, arguments) = if args.len() == 1 {
+        let [program] = expect_args(call_span, args)?;
+        (program, Vec::new())
+    } else {
+        let [program, arguments] = expect_args(call_span, args)?;
+        let span = arguments.span;
+        let arguments = arguments
+            .into_array()?
+            .iter()
+            .map(|value| match value {
+                Value::String(s) => Ok(s.to_string()),
+                value => Err(Argument::wrong_type(span, Type::String, value.clone())),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        (program, arguments)
+    };
+    let program_span = program.span;
+
+    let output = std::process::Command::new(program.into_string()?)
+        .args(arguments)
+        .envs(env.env_vars.borrow().iter())
+        .output()
+        .map_err(io_error(program_span))?;
+
+    Ok(Value::Map(Rc::new(HashMap::from([
+        (
+            Value::String(Rc::new("stdout".to_string())),
+            Value::String(Rc::new(
+                String::from_utf8_lossy(&output.stdout).into_owned(),
+            )),
+        ),
+        (
+            Value::String(Rc::new("stderr".to_string())),
+            Value::String(Rc::new(
+                String::from_utf8_lossy(&output.stderr).into_owned(),
+            )),
+        ),
+        (
+            Value::String(Rc::new("code".to_string())),
+            output
+                .status
+                .code()
+                .map(|code| Value::Int(code.into()))
+                .unwrap_or(Value::Null),
+        ),
+    ]))))
+}
+
+/// Unpacks the `(string, width, fill?)` arguments shared by the padding intrinsics, returning the
+/// string along with the padding to add to it.
+fn padding<'a>(call_span: Span, args: Vec<Argument<'a>>) -> Result<'a, (String, String)> {
+    let (string, width, fill) = if args.len() == 2 {
+        let [string, width] = expect_args(call_span, args)?;
+        (string.into_string()?, width.into_int()?, ' ')
+    } else {
+        let [string, width, fill] = expect_args(call_span, args)?;
+        let fill_span = fill.span;
+        let fill = fill.into_string()?;
+        let mut chars = fill.chars();
+        let (Some(c), None) = (chars.next(), chars.next()) else {
+            return Err(Error::new(fill_span, ErrorKind::NotAChar(fill)));
+        };
+        (string.into_string()?, width.into_int()?, c)
+    };
+    let len = string.chars().count();
+    let padding = std::iter::repeat_n(fill, (width.max(0) as usize).saturating_sub(len)).collect();
+    Ok((string, padding))
+}
+
+fn pad_left<'a>(
+    _env: &mut Environment<'a>,
+    call_span: Span,
+    args: Vec<Argument<'a>>,
+) -> Result<'a, Value<'a>> {
+    let (string, padding) = padding(call_span, args)?;
+    Ok(Value::String(Rc::new(padding + &string)))
+}
+
+fn pad_right<'a>(
+    _env: &mut Environment<'a>,
+    call_span: Span,
+    args: Vec<Argument<'a>>,
+) -> Result<'a, Value<'a>> {
+    let (string, padding) = padding(call_span, args)?;
+    Ok(Value::String(Rc::new(string + &padding)))
+}
+
+fn lines<'a>(
+    _env: &mut Environment<'a>,
+    call_span: Span,
+    args: Vec<Argument<'a>>,
+) -> Result<'a, Value<'a>> {
+    let [string] = expect_args(call_span, args)?;
+    Ok(Value::Array(Rc::new(
+        string
+            .into_string()?
+            .lines()
+            .map(|line| Value::String(Rc::new(line.to_string())))
+            .collect(),
+    )))
+}
+
+fn len<'a>(
+    _env: &mut Environment<'a>,
+    call_span: Span,
+    args: Vec<Argument<'a>>,
+) -> Result<'a, Value<'a>> {
+    let [value] = expect_args(call_span, args)?;
+    let len = match value.value {
+        Value::String(s) => s.chars().count(),
+        Value::Bytes(b) => b.len(),
+        Value::Array(a) => a.len(),
+        Value::Map(m) => m.len(),
+        other => return Err(Error::new(value.span, ErrorKind::NoLength(other.into()))),
+    };
+    Ok(Value::Int(len as i64))
+}
+
+/// Converts a string (as UTF-8) or an array of integers into bytes.
+fn bytes<'a>(
+    _env: &mut Environment<'a>,
+    call_span: Span,
+    args: Vec<Argument<'a>>,
+) -> Result<'a, Value<'a>> {
+    let [value] = expect_args(call_span, args)?;
+    let bytes = match value.value {
+        Value::String(s) => s.as_bytes().to_vec(),
+        Value::Bytes(b) => return Ok(Value::Bytes(b)),
+        Value::Array(a) => {
+            a.iter()
+                .map(|element| match element {
+                    Value::Int(i) => u8::try_from(*i)
+                        .map_err(|_| Error::new(value.span, ErrorKind::InvalidByte(*i))),
+                    element => Err(Argument::wrong_type(value.span, Type::Int, element.clone())),
+                })
+                .collect::<Result<_>>()?
+        }
+        other => return Err(Argument::wrong_type(value.span, Type::Array, other)),
+    };
+    Ok(Value::Bytes(Rc::new(bytes)))
+}
+
+/// Returns the elements of an array or bytes from `start` up to (but not including) `end`.
+fn slice<'a>(
+    _env: &mut Environment<'a>,
+    call_span: Span,
+    args: Vec<Argument<'a>>,
+) -> Result<'a, Value<'a>> {
+    let [collection, start, end] = expect_args(call_span, args)?;
+    let len = match &collection.value {
+        Value::Array(a) => a.len(),
+        Value::Bytes(b) => b.len(),
+        _ => {
+            return Err(Argument::wrong_type(
+                collection.span,
+                Type::Array,
+                collection.value,
+            ));
+        }
+    };
+    let (start_span, end_span) = (start.span, end.span);
+    let start = array_position(start_span, len, start.into_int()?, true)?;
+    let end = array_position(end_span, len, end.into_int()?, true)?;
+    if start > end {
+        return Err(Error::new(
+            start_span.join(end_span),
+            ErrorKind::InvertedSlice { start, end },
+        ));
+    }
+    Ok(match collection.value {
+        Value::Array(a) => Value::Array(Rc::new(a[start..end].to_vec())),
+        Value::Bytes(b) => Value::Bytes(Rc::new(b[start..end].to_vec())),
+        _ => unreachable!(),
+    })
+}
+
+fn read_file_bytes<'a>(
+    _env: &mut Environment<'a>,
+    call_span: Span,
+    args: Vec<Argument<'a>>,
+) -> Result<'a, Value<'a>> {
+    let [path] = expect_args(call_span, args)?;
+    let bytes = std::fs::read(path.into_string()?).map_err(io_error(call_span))?;
+    Ok(Value::Bytes(Rc::new(bytes)))
+}
+
+fn write_file_bytes<'a>(
+    _env: &mut Environment<'a>,
+    call_span: Span,
+    args: Vec<Argument<'a>>,
+) -> Result<'a, Value<'a>> {
+    let [path, bytes] = expect_args(call_span, args)?;
+    let path = path.into_string()?;
+    std::fs::write(path, bytes.into_bytes()?.as_slice()).map_err(io_error(call_span))?;
+    Ok(Value::Null)
+}
+
+#[cfg(feature = "regex")]
+fn regex_match<'a>(
+    _env: &mut Environment<'a>,
+    call_span: Span,
+    args: Vec<Argument<'a>>,
+) -> Result<'a, Value<'a>> {
+    let [pattern, string] = expect_args(call_span, args)?;
+    let pattern = pattern.into_regex()?;
+    Ok(Value::Bool(pattern.is_match(&string.into_string()?)))
+}
+
+#[cfg(feature = "regex")]
+fn regex_find_all<'a>(
+    _env: &mut Environment<'a>,
+    call_span: Span,
+    args: Vec<Argument<'a>>,
+) -> Result<'a, Value<'a>> {
+    let [pattern, string] = expect_args(call_span, args)?;
+    let pattern = pattern.into_regex()?;
+    Ok(Value::Array(Rc::new(
+        pattern
+            .find_iter(&string.into_string()?)
+            .map(|m| Value::String(Rc::new(m.as_str().to_string())))
+            .collect(),
+    )))
+}
+
+#[cfg(feature = "regex")]
+fn regex_replace<'a>(
+    _env: &mut Environment<'a>,
+    call_span: Span,
+    args: Vec<Argument<'a>>,
+) -> Result<'a, Value<'a>> {
+    let [pattern, string, replacement] = expect_args(call_span, args)?;
+    let pattern = pattern.into_regex()?;
+    let replacement = replacement.into_string()?;
+    Ok(Value::String(Rc::new(
+        pattern
+            .replace_all(&string.into_string()?, replacement.as_str())
+            .into_owned(),
+    )))
+}
+
+#[cfg(feature = "json")]
+fn parse_json<'a>(
+    _env: &mut Environment<'a>,
+    call_span: Span,
+    args: Vec<Argument<'a>>,
+) -> Result<'a, Value<'a>> {
+    let [string] = expect_args(call_span, args)?;
+    let string_span = string.span;
+    let json: serde_json::Value = serde_json::from_str(&string.into_string()?)
+        .map_err(|e| Error::new(string_span, ErrorKind::InvalidJson(e)))?;
+    Ok(json.into())
+}
+
+#[cfg(feature = "json")]
+fn to_json<'a>(
+    _env: &mut Environment<'a>,
+    call_span: Span,
+    args: Vec<Argument<'a>>,
+) -> Result<'a, Value<'a>> {
+    let [value] = expect_args(call_span, args)?;
+    let json = serde_json::Value::try_from(value.value)
+        .map_err(|e| Error::new(value.span, ErrorKind::NotJson(e)))?;
+    Ok(Value::String(Rc::new(json.to_string())))
+}
+
+#[cfg(feature = "http")]
+fn http_get<'a>(
+    _env: &mut Environment<'a>,
+    call_span: Span,
+    args: Vec<Argument<'a>>,
+) -> Result<'a, Value<'a>> {
+    let [url] = expect_args(call_span, args)?;
+    let url_span = url.span;
+    let response = match ureq::get(&url.into_string()?).call() {
+        Ok(response) | Err(ureq::Error::Status(_, response)) => response,
+        Err(e) => {
+            return Err(Error::new(url_span, ErrorKind::Http(Box::new(e))));
+        }
+    };
+
+    let status = Value::Int(response.status().into());
+    let headers = Value::Map(Rc::new(
+        response
+            .headers_names()
+            .into_iter()
+            .filter_map(|name| {
+                let value = response.header(&name)?.to_string();
+                Some((Value::String(Rc::new(name)), Value::String(Rc::new(value))))
+            })
+            .collect(),
+    ));
+    let body = response.into_string().map_err(io_error(call_span))?;
+
+    Ok(Value::Map(Rc::new(HashMap::from([
+        (Value::String(Rc::new("status".to_string())), status),
+        (Value::String(Rc::new("headers".to_string())), headers),
+        (
+            Value::String(Rc::new("body".to_string())),
+            Value::String(Rc::new(body)),
+        ),
+    ]))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse::parse_program, sync::RefCell};
+
+    fn eval(source: &str) -> Result<'_, Value<'_>> {
+        Environment::default().eval(parse_program(source).unwrap())
+    }
+
+    /// What evaluating `source` fails with.
+    fn error(source: &str) -> ErrorKind<'_> {
+        match eval(source) {
+            Ok(value) => panic!("{source} evaluated to {value:?}"),
+            Err(error) => error.kind,
+        }
+    }
+
+    #[test]
+    fn pop_removes_the_last_element() {
+        assert_eq!(eval("pop([1, 2])").unwrap(), Value::from(vec![1]));
+        assert!(matches!(
+            error("pop([])"),
+            ErrorKind::IndexOutOfBounds { len: 0, index: 0 }
+        ));
+    }
+
+    #[test]
+    fn insert_allows_the_end() {
+        assert_eq!(eval("insert([1], 0, 0)").unwrap(), Value::from(vec![0, 1]));
+        assert_eq!(eval("insert([1], 1, 2)").unwrap(), Value::from(vec![1, 2]));
+        assert!(matches!(
+            error("insert([1], 2, 2)"),
+            ErrorKind::IndexOutOfBounds { len: 1, index: 2 }
+        ));
+        assert!(matches!(
+            error("insert([1], -1, 2)"),
+            ErrorKind::IndexOutOfBounds { len: 1, index: -1 }
+        ));
+    }
+
+    #[test]
+    fn remove_needs_an_element() {
+        assert_eq!(eval("remove([1, 2], 0)").unwrap(), Value::from(vec![2]));
+        assert!(matches!(
+            error("remove([1], 1)"),
+            ErrorKind::IndexOutOfBounds { len: 1, index: 1 }
+        ));
+    }
+
+    #[test]
+    fn arrays_are_not_changed_in_place() {
+        assert_eq!(
+            eval("let a = [1, 2]; pop(a); insert(a, 0, 0); remove(a, 0); a").unwrap(),
+            Value::from(vec![1, 2])
+        );
+    }
+
+    #[test]
+    fn range_counts_up_or_down() {
+        assert_eq!(eval("range(3)").unwrap(), Value::from(vec![0, 1, 2]));
+        assert_eq!(eval("range(1, 7, 2)").unwrap(), Value::from(vec![1, 3, 5]));
+        assert_eq!(eval("range(5, 0, -2)").unwrap(), Value::from(vec![5, 3, 1]));
+        assert_eq!(eval("range(3, 1)").unwrap(), Value::from(Vec::<i64>::new()));
+        assert_eq!(
+            eval("range(5, 0, -9223372036854775807 - 1)").unwrap(),
+            Value::from(vec![5])
+        );
+        assert!(matches!(error("range(0, 5, 0)"), ErrorKind::ZeroStep));
+    }
+
+    #[test]
+    fn take_and_drop_allow_the_whole_array() {
+        assert_eq!(eval("take([1, 2, 3], 2)").unwrap(), Value::from(vec![1, 2]));
+        assert_eq!(eval("take([1, 2], 2)").unwrap(), Value::from(vec![1, 2]));
+        assert_eq!(eval("drop([1, 2, 3], 2)").unwrap(), Value::from(vec![3]));
+        assert_eq!(
+            eval("drop([1, 2], 2)").unwrap(),
+            Value::from(Vec::<i64>::new())
+        );
+    }
+
+    #[test]
+    fn take_and_drop_check_their_count() {
+        for (source, len, count) in [
+            ("take([1, 2], 3)", 2, 3),
+            ("take([1], -1)", 1, -1),
+            ("drop([1, 2], 4)", 2, 4),
+            ("drop([], -1)", 0, -1),
+        ] {
+            assert!(
+                matches!(
+                    error(source),
+                    ErrorKind::IndexOutOfBounds { len: l, index } if l == len && index == count
+                ),
+                "{source}"
+            );
+        }
+    }
+
+    #[test]
+    fn chunk_and_windows_need_a_positive_size() {
+        assert_eq!(
+            eval("chunk([1, 2, 3], 2)").unwrap(),
+            Value::from(vec![vec![1, 2], vec![3]])
+        );
+        assert_eq!(
+            eval("windows([1, 2, 3], 2)").unwrap(),
+            Value::from(vec![vec![1, 2], vec![2, 3]])
+        );
+        assert_eq!(
+            eval("windows([1], 2)").unwrap(),
+            Value::from(Vec::<i64>::new())
+        );
+        assert!(matches!(
+            error("chunk([1], 0)"),
+            ErrorKind::NonPositiveSize(0)
+        ));
+        assert!(matches!(
+            error("windows([1], -1)"),
+            ErrorKind::NonPositiveSize(-1)
+        ));
+    }
+
+    #[test]
+    fn predicates_stop_at_the_first_decisive_element() {
+        assert_eq!(
+            eval("find([1, 2, 3], fn(x) { x > 1 })").unwrap(),
+            Value::Int(2)
+        );
+        assert_eq!(eval("find([1], fn(x) { x > 1 })").unwrap(), Value::Null);
+        assert_eq!(
+            eval("any([1, 2], fn(x) { x > 1 })").unwrap(),
+            Value::Bool(true)
+        );
+        assert_eq!(
+            eval("all([1, 2], fn(x) { x > 1 })").unwrap(),
+            Value::Bool(false)
+        );
+        // The division by zero is never reached.
+        assert_eq!(
+            eval("any([0, 1], fn(x) { 1 / (1 - x) })").unwrap(),
+            Value::Bool(true)
+        );
+    }
+
+    #[test]
+    fn predicates_of_empty_arrays() {
+        assert_eq!(eval("find([], fn(x) { true })").unwrap(), Value::Null);
+        assert_eq!(eval("any([], fn(x) { true })").unwrap(), Value::Bool(false));
+        assert_eq!(eval("all([], fn(x) { false })").unwrap(), Value::Bool(true));
+    }
+
+    #[test]
+    fn predicates_must_be_functions() {
+        assert!(matches!(
+            error("any([1], 1)"),
+            ErrorKind::WrongArgumentType {
+                expected: Type::Function,
+                found: Type::Int
+            }
+        ));
+    }
+
+    #[test]
+    fn clone_shares_nothing() {
+        /// Whether the arrays in the two elements of `source`'s array share their storage.
+        fn shared(source: &str) -> bool {
+            let Ok(Value::Array(pair)) = eval(source) else {
+                panic!("{source} is an array");
+            };
+            let [Value::Array(first), Value::Array(second)] = &pair[..] else {
+                panic!("{source} is a pair of arrays");
+            };
+            Rc::ptr_eq(first, second)
+        }
+
+        assert!(shared("let a = [[1]]; [a[0], a[0]]"));
+        assert!(!shared("let a = [[1]]; [a[0], clone(a)[0]]"));
+        assert_eq!(
+            eval("let a = [[1], {\"a\": [2]}]; a == clone(a)").unwrap(),
+            Value::Bool(true)
+        );
+    }
+
+    #[test]
+    fn padding_fills_to_a_width() {
+        assert_eq!(
+            eval(r#"pad_left("7", 3, "0")"#).unwrap(),
+            Value::from("007")
+        );
+        assert_eq!(eval(r#"pad_right("ab", 4)"#).unwrap(), Value::from("ab  "));
+        assert_eq!(eval(r#"pad_right("abc", 1)"#).unwrap(), Value::from("abc"));
+        assert!(matches!(
+            error(r#"pad_left("a", 3, "xy")"#),
+            ErrorKind::NotAChar(fill) if fill == "xy"
+        ));
+    }
+
+    #[test]
+    fn lines_handles_both_line_endings() {
+        assert_eq!(
+            eval(r#"lines("a\nb\r\nc\n")"#).unwrap(),
+            Value::from(vec!["a", "b", "c"])
+        );
+    }
+
+    #[test]
+    fn printing_writes_to_the_environment_output() {
+        let output = Rc::new(RefCell::new(Vec::new()));
+        let mut env = Environment {
+            output: Some(output.clone()),
+            ..Default::default()
+        };
+        let program =
+            parse_program(r#"print(1, "a"); println([1, "b"]); dbg("c"); println()"#).unwrap();
+        env.eval(program).unwrap();
+        assert_eq!(
+            String::from_utf8(output.borrow().clone()).unwrap(),
+            "1 a[1, b]\n\"c\"\n\n"
+        );
+    }
+
+    #[test]
+    fn bytes_from_strings_and_arrays() {
+        assert_eq!(
+            eval(r#"bytes("hé")"#).unwrap(),
+            Value::Bytes(Rc::new("hé".as_bytes().to_vec()))
+        );
+        assert_eq!(eval(r#"bytes("hi")[1]"#).unwrap(), Value::Int(105));
+        assert_eq!(
+            eval("bytes([104, 105]) == bytes(\"hi\")").unwrap(),
+            Value::Bool(true)
+        );
+        assert!(matches!(
+            error("bytes([104, 256])"),
+            ErrorKind::InvalidByte(256)
+        ));
+    }
+
+    #[test]
+    fn exit_stops_evaluation_with_a_code() {
+        assert!(matches!(error("exit(3); println(1)"), ErrorKind::Exit(3)));
+        assert!(matches!(error("exit()"), ErrorKind::Exit(0)));
+        assert!(matches!(
+            error("exit(4294967296)"),
+            ErrorKind::InvalidExitCode(4294967296)
+        ));
+    }
+
+    #[test]
+    fn set_env_only_changes_the_environment() {
+        assert_eq!(
+            eval(r#"set_env("MONKEY_TEST_VAR", "1"); env("MONKEY_TEST_VAR")"#).unwrap(),
+            Value::String(Rc::new("1".to_string()))
+        );
+        assert!(std::env::var("MONKEY_TEST_VAR").is_err());
+        assert_eq!(eval(r#"env("MONKEY_TEST_VAR")"#).unwrap(), Value::Null);
+        assert!(matches!(
+            error(r#"set_env("A=B", "1")"#),
+            ErrorKind::InvalidEnvVar(_)
+        ));
     }
-    println!("{}", args[0]);
-    Ok(args.into_iter().next().unwrap())
 }
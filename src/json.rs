@@ -0,0 +1,70 @@
+//! Conversions between values and JSON, for `parse_json` and `to_json`.
+
+use crate::{
+    sync::Rc,
+    value::{Type, Value},
+};
+
+impl From<serde_json::Value> for Value<'_> {
+    /// Numbers that aren't integers are truncated, saturating at the bounds of an `i64`.
+    fn from(value: serde_json::Value) -> Self {
+        match value {
+            serde_json::Value::Null => Self::Null,
+            serde_json::Value::Bool(b) => Self::Bool(b),
+            serde_json::Value::Number(n) => Self::Int(
+                n.as_i64()
+                    .unwrap_or_else(|| n.as_f64().unwrap_or_default() as i64),
+            ),
+            serde_json::Value::String(s) => Self::String(Rc::new(s)),
+            serde_json::Value::Array(a) => {
+                Self::Array(Rc::new(a.into_iter().map(Self::from).collect()))
+            }
+            serde_json::Value::Object(o) => Self::Map(Rc::new(
+                o.into_iter()
+                    .map(|(k, v)| (Self::String(Rc::new(k)), Self::from(v)))
+                    .collect(),
+            )),
+        }
+    }
+}
+
+/// A value with no JSON representation.
+#[derive(thiserror::Error, Debug)]
+pub enum NotJson {
+    #[error("{0} cannot be converted to JSON")]
+    Value(Type),
+    #[error("{0} cannot be used as a JSON object key")]
+    Key(Type),
+}
+
+impl TryFrom<Value<'_>> for serde_json::Value {
+    type Error = NotJson;
+
+    /// Fails on bytes, functions, errors, and map keys that aren't strings.
+    fn try_from(value: Value<'_>) -> Result<Self, Self::Error> {
+        Ok(match value {
+            Value::Null => Self::Null,
+            Value::Bool(b) => Self::Bool(b),
+            Value::Int(i) => Self::Number(i.into()),
+            Value::String(s) => Self::String(Rc::unwrap_or_clone(s)),
+            Value::Array(a) => Self::Array(
+                Rc::unwrap_or_clone(a)
+                    .into_iter()
+                    .map(Self::try_from)
+                    .collect::<Result<_, _>>()?,
+            ),
+            Value::Map(m) => Self::Object(
+                Rc::unwrap_or_clone(m)
+                    .into_iter()
+                    .map(|(k, v)| match k {
+                        Value::String(k) => Ok((Rc::unwrap_or_clone(k), Self::try_from(v)?)),
+                        k => Err(NotJson::Key(k.into())),
+                    })
+                    .collect::<Result<_, _>>()?,
+            ),
+            value @ (Value::Bytes(_) | Value::Function(_) | Value::Error(_)) => {
+                return Err(NotJson::Value(value.into()));
+            }
+        })
+    }
+}
@@ -0,0 +1,45 @@
+//! An interpreter for the Monkey programming language.
+//!
+//! Source is parsed with [`parse_program`] into a [`Program`], which an [`Environment`] evaluates
+//! to a [`Value`]. Errors from either step, and warnings from [`lint::check`], can be printed with
+//! the source they point at by their `report` methods. Programs can call Rust closures added with
+//! [`Environment::register`] and use values bound with [`Environment::define`], and [`convert`]
+//! turns values to and from Rust types.
+//!
+//! ```
+//! use monkey::{Environment, Value, parse_program};
+//!
+//! let program = parse_program("let double = fn(x) { x * 2 }; double(21)").unwrap();
+//! let mut env = Environment::default();
+//! assert!(matches!(env.eval(program), Ok(Value::Int(42))));
+//! ```
+//!
+//! The `monkey` binary is a command-line interface and REPL over this crate. Its dependencies are
+//! behind the default `cli` feature, so embedders can turn that off. The other features add
+//! intrinsics (`regex`, `http`, `json`), make values thread-safe (`sync`), or build the language
+//! server (`lsp`), the browser interface (`wasm`), and fuzzing support (`arbitrary`).
+
+#[cfg(feature = "arbitrary")]
+pub mod arbitrary;
+pub mod ast;
+pub mod convert;
+pub mod eval;
+pub mod intrinsic;
+#[cfg(feature = "json")]
+pub mod json;
+pub mod lint;
+#[cfg(feature = "lsp")]
+pub mod lsp;
+pub mod parse;
+pub mod reparse;
+pub mod report;
+mod resolve;
+pub mod sync;
+pub mod value;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+pub use ast::Program;
+pub use eval::{Environment, Error};
+pub use parse::{Error as ParseError, parse_program};
+pub use value::Value;
@@ -0,0 +1,228 @@
+//! Checks for likely mistakes in a program that still let it run.
+
+use crate::{
+    ast::*,
+    parse::Program,
+    report::Source,
+    resolve::{Resolve, resolve},
+};
+use strum::EnumDiscriminants;
+
+/// A likely mistake that doesn't stop a program from running.
+pub struct Warning<'a> {
+    pub span: Span,
+    pub kind: WarningKind<'a>,
+}
+
+#[derive(thiserror::Error, Debug, EnumDiscriminants)]
+#[strum_discriminants(name(Lint), cfg_attr(feature = "cli", derive(clap::ValueEnum)))]
+pub enum WarningKind<'a> {
+    #[error("unused binding `{0}`")]
+    Unused(&'a str),
+    #[error("unreachable statement")]
+    Unreachable {
+        /// The return statement that makes the code unreachable.
+        return_span: Span,
+    },
+    #[error("`{name}` shadows an earlier binding")]
+    Shadowed { name: &'a str, previous: Span },
+}
+
+impl Warning<'_> {
+    /// Reports the warning, as an error if `strict` is set.
+    pub fn report(&self, source: Source, strict: bool) {
+        self.write_report(source, strict, std::io::stderr());
+    }
+
+    /// Like [`report`](Self::report), but writes to `out` instead of stderr.
+    pub fn write_report(&self, source: Source, strict: bool, out: impl std::io::Write) {
+        use ariadne::{Color, Label, ReportKind};
+
+        let kind = if strict {
+            ReportKind::Error
+        } else {
+            ReportKind::Warning
+        };
+        let mut builder = source
+            .build_report(kind, self.span)
+            .with_message(&self.kind)
+            .with_label(Label::new(source.span(self.span)).with_color(Color::Yellow));
+        match self.kind {
+            WarningKind::Unused(_) => {}
+            WarningKind::Unreachable { return_span } => {
+                builder = builder.with_label(
+                    Label::new(source.span(return_span))
+                        .with_message("any code following this return is unreachable")
+                        .with_color(Color::Blue),
+                );
+            }
+            WarningKind::Shadowed { previous, .. } => {
+                builder = builder.with_label(
+                    Label::new(source.span(previous))
+                        .with_message("previously bound here")
+                        .with_color(Color::Blue),
+                );
+            }
+        }
+        source.write(builder, out);
+    }
+}
+
+/// Finds likely mistakes in `program`, in source order.
+pub fn check<'a>(program: &Program<'a>) -> Vec<Warning<'a>> {
+    let mut checker = Checker::default();
+    resolve(&program.statements, &mut checker);
+    checker.warnings.sort_by_key(|warning| warning.span.start);
+    checker.warnings
+}
+
+struct Binding {
+    span: Span,
+    used: bool,
+}
+
+#[derive(Default)]
+struct Checker<'a> {
+    warnings: Vec<Warning<'a>>,
+}
+
+impl<'p, 'a> Resolve<'p, 'a> for Checker<'a> {
+    type Binding = Binding;
+
+    fn bind(
+        &mut self,
+        name: &'p Identifier<'a>,
+        value: Option<&'p Expression<'a>>,
+        shadowed: Option<&Binding>,
+    ) -> Binding {
+        // Arguments are bound by the caller, so not using them or shadowing with them is fine.
+        if value.is_some()
+            && let Some(previous) = shadowed
+        {
+            self.warn(
+                name.span,
+                WarningKind::Shadowed {
+                    name: name.name,
+                    previous: previous.span,
+                },
+            );
+        }
+        Binding {
+            span: name.span,
+            used: value.is_none(),
+        }
+    }
+
+    fn reference(&mut self, _name: &'p Identifier<'a>, binding: Option<&mut Binding>) {
+        if let Some(binding) = binding {
+            binding.used = true;
+        }
+    }
+
+    fn unbind(&mut self, name: &'a str, binding: Binding) {
+        if !binding.used {
+            self.warn(binding.span, WarningKind::Unused(name));
+        }
+    }
+
+    fn unreachable(&mut self, return_span: Span, span: Span) {
+        self.warn(span, WarningKind::Unreachable { return_span });
+    }
+}
+
+impl<'a> Checker<'a> {
+    fn warn(&mut self, span: Span, kind: WarningKind<'a>) {
+        self.warnings.push(Warning { span, kind });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::parse_program;
+
+    /// The warnings about `source`, with the text each one points at.
+    fn lints(source: &str) -> Vec<(Lint, &str)> {
+        check(&parse_program(source).unwrap())
+            .iter()
+            .map(|warning| {
+                let Span { start, end } = warning.span;
+                (Lint::from(&warning.kind), &source[start..end])
+            })
+            .collect()
+    }
+
+    #[test]
+    fn unused_bindings_are_reported() {
+        assert_eq!(lints("let unused = 1;"), [(Lint::Unused, "unused")]);
+        assert_eq!(
+            lints("let f = fn() { let inner = 1; 2 }; f()"),
+            [(Lint::Unused, "inner")]
+        );
+    }
+
+    #[test]
+    fn parameters_and_used_bindings_are_fine() {
+        assert_eq!(lints("let x = 1; let f = fn(x, y) { x }; f(x, 2)"), []);
+    }
+
+    #[test]
+    fn functions_can_use_later_bindings() {
+        assert_eq!(lints("let f = fn() { g() }; let g = fn() { 1 }; f()"), []);
+    }
+
+    #[test]
+    fn shadowing_is_reported() {
+        assert_eq!(
+            lints("let x = 1; let f = fn() { let x = 2; x }; f() + x"),
+            [(Lint::Shadowed, "x")]
+        );
+        assert_eq!(
+            lints("let x = 1; let x = x + 1; x"),
+            [(Lint::Shadowed, "x")]
+        );
+    }
+
+    #[test]
+    fn statements_after_a_return_are_unreachable() {
+        assert_eq!(
+            lints("let f = fn() { return 1; 2; 3 }; f()"),
+            [(Lint::Unreachable, "2; 3")]
+        );
+        assert_eq!(
+            lints("let f = fn(n) { if (n) { return 1; } else { return 2; } }; f(1)"),
+            []
+        );
+    }
+
+    #[test]
+    fn warnings_are_in_source_order() {
+        // Function bodies are checked after the code around them.
+        assert_eq!(
+            lints("let f = fn() { let b = 2; return 3; 4 }; let a = 1; f()"),
+            [
+                (Lint::Unused, "b"),
+                (Lint::Unreachable, "4"),
+                (Lint::Unused, "a")
+            ]
+        );
+    }
+
+    #[test]
+    fn strict_mode_reports_warnings_as_errors() {
+        crate::report::set_color(crate::report::ColorChoice::Never);
+        let text = "let unused = 1;";
+        let program = parse_program(text).unwrap();
+        let source = Source {
+            name: "test.mk",
+            text,
+        };
+        let report = |strict| {
+            let mut out = Vec::new();
+            check(&program)[0].write_report(source, strict, &mut out);
+            String::from_utf8(out).unwrap()
+        };
+        assert!(report(false).starts_with("Warning: unused binding `unused`"));
+        assert!(report(true).starts_with("Error: unused binding `unused`"));
+    }
+}
@@ -0,0 +1,403 @@
+//! A language server for editors, speaking LSP over stdin and stdout.
+//!
+//! Documents are kept parsed between edits, and editors send only what changed, so that only the
+//! statements around each edit are parsed again.
+
+use crate::{
+    ast::*,
+    intrinsic::INTRINSICS,
+    lint::{self, WarningKind},
+    parse::{self, parse_program},
+    reparse::{Edit, attach, detach, reparse},
+    resolve::{Resolve, resolve},
+    value::Type,
+};
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::{
+    CompletionItem, CompletionItemKind, CompletionOptions, CompletionParams, CompletionResponse,
+    Diagnostic, DiagnosticRelatedInformation, DiagnosticSeverity, DidChangeTextDocumentParams,
+    DidCloseTextDocumentParams, DidOpenTextDocumentParams, GotoDefinitionParams,
+    GotoDefinitionResponse, Hover, HoverContents, HoverParams, HoverProviderCapability, Location,
+    MarkedString, OneOf, Position, PublishDiagnosticsParams, Range, ServerCapabilities,
+    TextDocumentContentChangeEvent, TextDocumentPositionParams, TextDocumentSyncKind, Uri,
+    notification::{
+        DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, Notification as _,
+        PublishDiagnostics,
+    },
+    request::{Completion, GotoDefinition, HoverRequest, Request as _},
+};
+use std::collections::HashMap;
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Each open document, by URI.
+type Documents = HashMap<String, Document>;
+
+struct Document {
+    text: String,
+    /// What `text` parses to, [`detach`]ed from it so that the two can be kept together.
+    parsed: std::result::Result<Program<'static>, Vec<parse::Error>>,
+}
+
+impl Document {
+    fn new(text: String) -> Self {
+        let parsed = parse_program(&text).map(|program| detach(&program));
+        Self { text, parsed }
+    }
+
+    /// Applies a change from the editor, parsing again only around it if the document parsed
+    /// before. Returns `None` if the change is to a part of the document that doesn't exist.
+    fn change(&mut self, change: TextDocumentContentChangeEvent) -> Option<()> {
+        let Some(range) = change.range else {
+            *self = Self::new(change.text);
+            return Some(());
+        };
+        let edit = Edit {
+            span: Span {
+                start: offset(&self.text, range.start)?,
+                end: offset(&self.text, range.end)?,
+            },
+            text: &change.text,
+        };
+        let text = edit.apply(&self.text);
+        let parsed = match &self.parsed {
+            Ok(program) => reparse(program, &edit, &text),
+            Err(_) => parse_program(&text),
+        }
+        .map(|program| detach(&program));
+        *self = Self { text, parsed };
+        Some(())
+    }
+
+    /// The document's program, if it parses.
+    fn program(&self) -> Option<Program<'_>> {
+        let program = self.parsed.as_ref().ok()?;
+        Some(attach(program, &self.text))
+    }
+}
+
+/// Serves requests until the editor shuts the server down.
+pub fn serve() -> Result<()> {
+    let (connection, io_threads) = Connection::stdio();
+    let capabilities = ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncKind::INCREMENTAL.into()),
+        definition_provider: Some(OneOf::Left(true)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        completion_provider: Some(CompletionOptions::default()),
+        ..Default::default()
+    };
+    connection.initialize(serde_json::to_value(capabilities)?)?;
+
+    let mut documents = HashMap::new();
+    for message in &connection.receiver {
+        match message {
+            Message::Request(request) => {
+                if connection.handle_shutdown(&request)? {
+                    break;
+                }
+                let response = respond(&documents, request);
+                connection.sender.send(response.into())?;
+            }
+            Message::Notification(notification) => {
+                if let Some(params) = update(&mut documents, notification) {
+                    let notification = Notification::new(PublishDiagnostics::METHOD.into(), params);
+                    connection.sender.send(notification.into())?;
+                }
+            }
+            Message::Response(_) => {}
+        }
+    }
+    drop(connection);
+    io_threads.join()?;
+    Ok(())
+}
+
+/// Keeps `documents` in sync with the editor, returning fresh diagnostics for whichever document
+/// changed.
+fn update(
+    documents: &mut Documents,
+    notification: Notification,
+) -> Option<PublishDiagnosticsParams> {
+    let uri = match notification.method.as_str() {
+        DidOpenTextDocument::METHOD => {
+            let params: DidOpenTextDocumentParams =
+                serde_json::from_value(notification.params).ok()?;
+            let uri = params.text_document.uri;
+            documents.insert(uri.to_string(), Document::new(params.text_document.text));
+            uri
+        }
+        DidChangeTextDocument::METHOD => {
+            let params: DidChangeTextDocumentParams =
+                serde_json::from_value(notification.params).ok()?;
+            let uri = params.text_document.uri;
+            let document = documents.get_mut(uri.as_str())?;
+            // Each change is to the document as the changes before it left it.
+            for change in params.content_changes {
+                document.change(change)?;
+            }
+            uri
+        }
+        DidCloseTextDocument::METHOD => {
+            let params: DidCloseTextDocumentParams =
+                serde_json::from_value(notification.params).ok()?;
+            documents.remove(params.text_document.uri.as_str());
+            return Some(PublishDiagnosticsParams::new(
+                params.text_document.uri,
+                Vec::new(),
+                None,
+            ));
+        }
+        _ => return None,
+    };
+    let diagnostics = diagnostics(&uri, documents.get(uri.as_str())?);
+    Some(PublishDiagnosticsParams::new(uri, diagnostics, None))
+}
+
+fn respond(documents: &Documents, request: Request) -> Response {
+    fn handle<R: lsp_types::request::Request>(
+        request: Request,
+        handler: impl FnOnce(R::Params) -> R::Result,
+    ) -> Response {
+        match serde_json::from_value(request.params) {
+            Ok(params) => Response::new_ok(request.id, handler(params)),
+            Err(err) => {
+                Response::new_err(request.id, ErrorCode::InvalidParams as i32, err.to_string())
+            }
+        }
+    }
+
+    match request.method.as_str() {
+        GotoDefinition::METHOD => {
+            handle::<GotoDefinition>(request, |params| definition(documents, params))
+        }
+        HoverRequest::METHOD => handle::<HoverRequest>(request, |params| hover(documents, params)),
+        Completion::METHOD => handle::<Completion>(request, |params| completion(documents, params)),
+        method => Response::new_err(
+            request.id,
+            ErrorCode::MethodNotFound as i32,
+            format!("unsupported request `{method}`"),
+        ),
+    }
+}
+
+/// Syntax errors, or lint warnings if there are none.
+fn diagnostics(uri: &Uri, document: &Document) -> Vec<Diagnostic> {
+    let text = &document.text;
+    let program = match &document.parsed {
+        Ok(program) => attach(program, text),
+        Err(errors) => {
+            return errors
+                .iter()
+                .map(|error| {
+                    let message = match error.help {
+                        Some(help) => format!("{}\n{help}", error.kind),
+                        None => error.kind.to_string(),
+                    };
+                    diagnostic(text, error.span, DiagnosticSeverity::ERROR, message)
+                })
+                .collect();
+        }
+    };
+
+    let severity = if program.strict {
+        DiagnosticSeverity::ERROR
+    } else {
+        DiagnosticSeverity::WARNING
+    };
+    lint::check(&program)
+        .iter()
+        .map(|warning| {
+            let mut diagnostic = diagnostic(text, warning.span, severity, warning.kind.to_string());
+            let related = match warning.kind {
+                WarningKind::Unused(_) => None,
+                WarningKind::Unreachable { return_span } => {
+                    Some((return_span, "any code following this return is unreachable"))
+                }
+                WarningKind::Shadowed { previous, .. } => Some((previous, "previously bound here")),
+            };
+            diagnostic.related_information = related.map(|(span, message)| {
+                vec![DiagnosticRelatedInformation {
+                    location: Location::new(uri.clone(), range(text, span)),
+                    message: message.into(),
+                }]
+            });
+            diagnostic
+        })
+        .collect()
+}
+
+fn diagnostic(text: &str, span: Span, severity: DiagnosticSeverity, message: String) -> Diagnostic {
+    Diagnostic {
+        range: range(text, span),
+        severity: Some(severity),
+        source: Some("monkey".into()),
+        message,
+        ..Default::default()
+    }
+}
+
+fn definition(
+    documents: &Documents,
+    params: GotoDefinitionParams,
+) -> Option<GotoDefinitionResponse> {
+    let TextDocumentPositionParams {
+        text_document,
+        position,
+    } = params.text_document_position_params;
+    let document = documents.get(text_document.uri.as_str())?;
+    let text = &document.text;
+    let program = document.program()?;
+    let offset = offset(text, position)?;
+    let binding = reference_at(&program, offset)?.binding?;
+    Some(GotoDefinitionResponse::Scalar(Location::new(
+        text_document.uri,
+        range(text, binding.span),
+    )))
+}
+
+fn hover(documents: &Documents, params: HoverParams) -> Option<Hover> {
+    let TextDocumentPositionParams {
+        text_document,
+        position,
+    } = params.text_document_position_params;
+    let document = documents.get(text_document.uri.as_str())?;
+    let text = &document.text;
+    let program = document.program()?;
+    let offset = offset(text, position)?;
+    let reference = reference_at(&program, offset)?;
+
+    let kind = match reference.binding {
+        Some(Binding {
+            value: Some(value), ..
+        }) => kind(value).unwrap_or_else(|| "binding".into()),
+        Some(Binding { value: None, .. }) => "parameter".into(),
+        None if INTRINSICS.iter().any(|(name, _)| *name == reference.name) => {
+            "builtin function".into()
+        }
+        None => return None,
+    };
+    Some(Hover {
+        contents: HoverContents::Scalar(MarkedString::String(format!(
+            "{}: {kind}",
+            reference.name
+        ))),
+        range: Some(range(text, reference.span)),
+    })
+}
+
+/// The kind of value `value` evaluates to, if that can be told without evaluating it.
+fn kind(value: &Expression) -> Option<String> {
+    let ty = match value {
+        Expression::Integer { .. } => Type::Int,
+        Expression::Boolean { .. } => Type::Bool,
+        Expression::String { .. } => Type::String,
+        Expression::Array { .. } => Type::Array,
+        Expression::Map { .. } => Type::Map,
+        Expression::Null(_) => Type::Null,
+        Expression::Function { parameters, .. } => {
+            let parameters: Vec<_> = parameters.iter().map(|parameter| parameter.name).collect();
+            return Some(format!("{} fn({})", Type::Function, parameters.join(", ")));
+        }
+        _ => return None,
+    };
+    Some(ty.to_string())
+}
+
+fn completion(documents: &Documents, params: CompletionParams) -> Option<CompletionResponse> {
+    documents.get(params.text_document_position.text_document.uri.as_str())?;
+    let items = INTRINSICS
+        .iter()
+        .map(|(name, _)| CompletionItem {
+            label: name.to_string(),
+            kind: Some(CompletionItemKind::FUNCTION),
+            detail: Some("builtin function".into()),
+            ..Default::default()
+        })
+        .collect();
+    Some(CompletionResponse::Array(items))
+}
+
+/// Where a name is bound.
+#[derive(Clone, Copy)]
+struct Binding<'p, 'a> {
+    span: Span,
+    /// The value bound by a `let`, or `None` for parameters.
+    value: Option<&'p Expression<'a>>,
+}
+
+/// A use or binding of a name, and where the name is bound if it's bound by the script.
+struct Reference<'p, 'a> {
+    name: &'a str,
+    span: Span,
+    binding: Option<Binding<'p, 'a>>,
+}
+
+/// The name at `offset`, including just past its end, where the cursor sits after typing it.
+fn reference_at<'p, 'a>(program: &'p Program<'a>, offset: usize) -> Option<Reference<'p, 'a>> {
+    let mut references = Vec::new();
+    resolve(&program.statements, &mut references);
+    references
+        .into_iter()
+        .find(|reference| reference.span.start <= offset && offset <= reference.span.end)
+}
+
+/// Collects every use and binding of a name.
+impl<'p, 'a> Resolve<'p, 'a> for Vec<Reference<'p, 'a>> {
+    type Binding = Binding<'p, 'a>;
+
+    fn bind(
+        &mut self,
+        name: &'p Identifier<'a>,
+        value: Option<&'p Expression<'a>>,
+        _shadowed: Option<&Binding<'p, 'a>>,
+    ) -> Binding<'p, 'a> {
+        let binding = Binding {
+            span: name.span,
+            value,
+        };
+        self.push(Reference {
+            name: name.name,
+            span: name.span,
+            binding: Some(binding),
+        });
+        binding
+    }
+
+    fn reference(&mut self, name: &'p Identifier<'a>, binding: Option<&mut Binding<'p, 'a>>) {
+        self.push(Reference {
+            name: name.name,
+            span: name.span,
+            binding: binding.copied(),
+        });
+    }
+}
+
+/// Converts a byte offset into `text` to a position, whose columns count UTF-16 code units.
+fn position(text: &str, offset: usize) -> Position {
+    let before = text.get(..offset).unwrap_or(text);
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    Position::new(
+        before.matches('\n').count() as u32,
+        before[line_start..].encode_utf16().count() as u32,
+    )
+}
+
+/// Converts a position in `text` to a byte offset, or `None` if it's past the last line.
+fn offset(text: &str, position: Position) -> Option<usize> {
+    let line_start = match position.line {
+        0 => 0,
+        line => text.match_indices('\n').nth(line as usize - 1)?.0 + 1,
+    };
+    let mut column = 0;
+    for (i, c) in text[line_start..].char_indices() {
+        if column >= position.character || c == '\n' {
+            return Some(line_start + i);
+        }
+        column += c.len_utf16() as u32;
+    }
+    Some(text.len())
+}
+
+fn range(text: &str, span: Span) -> Range {
+    Range::new(position(text, span.start), position(text, span.end))
+}
@@ -1,25 +1,237 @@
-mod ast;
+mod bundle;
 mod cli;
-mod eval;
-mod intrinsic;
-mod parse;
-mod value;
+mod config;
+mod golden;
 
-use eval::Environment;
-use parse::parse_program;
+#[cfg(feature = "lsp")]
+use monkey::lsp;
+use monkey::{
+    eval::{Environment, Error, ErrorKind},
+    lint::{self, Lint},
+    parse::{self, Program, parse_program},
+    report::{self, Source},
+    sync, value,
+};
 use rustyline::error::ReadlineError;
-use value::Value;
+use std::{
+    path::Path,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
+};
+use sync::Rc;
+use value::{Type, Value};
+
+/// Exit status for programs that fail to parse or end in an error. Rust exits with 101 if the
+/// interpreter itself panics, which tells bugs in the interpreter apart from errors in programs.
+const EXIT_FAILURE: i32 = 1;
+
+/// Values that would print wider than this are spread over multiple lines in the REPL.
+const REPL_WIDTH: usize = 80;
 
 fn main() {
+    if let Some(text) = bundle::embedded_source() {
+        report::set_color(report::ColorChoice::Auto);
+        init_logging(0);
+        let source = Source {
+            name: "<embedded>",
+            text: &text,
+        };
+        let program = parse_or_exit(source);
+        let mut env = Environment {
+            args: std::env::args().skip(1).collect::<Vec<_>>().into(),
+            ..Default::default()
+        };
+        let code = run(source, program, &mut env);
+        if code != 0 {
+            std::process::exit(code);
+        }
+        return;
+    }
+
     let args = cli::parse();
-    if let Some(file) = args.file {
-        let contents = std::fs::read_to_string(file).unwrap();
-        let program = parse_program(&contents).unwrap();
-        eprintln!("{program}");
-        match Environment::default().eval(program) {
-            Err(e) => e.report(&contents),
-            Ok(Value::Null) => {}
-            Ok(value) => println!("{value}"),
+    report::set_color(args.color.unwrap_or_default());
+    init_logging(args.verbose);
+
+    // Evaluation recurses on the native stack, so scripts run on a thread whose stack has a known
+    // size, which limits how much of it they may use.
+    let stack_size = args.stack_size;
+    let thread = std::thread::Builder::new()
+        .stack_size(stack_size.get() * 1024 * 1024)
+        .spawn(move || run_cli(args));
+    match thread.map(|thread| thread.join()) {
+        Ok(Ok(())) => {}
+        Ok(Err(panic)) => std::panic::resume_unwind(panic),
+        Err(err) => {
+            eprintln!("Error: couldn't start a thread with a {stack_size} MiB stack: {err}");
+            std::process::exit(EXIT_FAILURE);
+        }
+    }
+}
+
+fn run_cli(mut args: cli::Args) {
+    let max_stack = args.max_stack();
+    if let Some(cli::Command::Build { file, output }) = args.command {
+        if let Err(err) = bundle::build(&file, &output) {
+            eprintln!("Error: {err}");
+            std::process::exit(EXIT_FAILURE);
+        }
+        return;
+    }
+
+    #[cfg(feature = "lsp")]
+    if let Some(cli::Command::Lsp) = args.command {
+        if let Err(err) = lsp::serve() {
+            eprintln!("Error: {err}");
+            std::process::exit(EXIT_FAILURE);
+        }
+        return;
+    }
+
+    if let Some(cli::Command::Check {
+        file,
+        strict,
+        allow,
+    }) = args.command
+    {
+        let (contents, name) = read_or_exit(&file);
+        let source = Source {
+            name: &name,
+            text: &contents,
+        };
+        let program = parse_or_exit(source);
+        if !lint(source, &program, strict, &allow) {
+            std::process::exit(EXIT_FAILURE);
+        }
+        return;
+    }
+
+    if let Some(cli::Command::Test {
+        examples: Some(dir),
+        bless,
+    }) = args.command
+    {
+        match golden::run(&dir, args.max_depth.unwrap_or(usize::MAX), max_stack, bless) {
+            Ok(true) => {}
+            Ok(false) => std::process::exit(EXIT_FAILURE),
+            Err(err) => {
+                eprintln!(
+                    "Error: couldn't run the examples in {}: {err}",
+                    dir.display()
+                );
+                std::process::exit(EXIT_FAILURE);
+            }
+        }
+        return;
+    }
+
+    if let Some(cli::Command::Bench {
+        file,
+        runs,
+        args: script_args,
+    }) = args.command
+    {
+        let (contents, name) = read_or_exit(&file);
+        let source = Source {
+            name: &name,
+            text: &contents,
+        };
+        let program = parse_or_exit(source);
+        let script_args: Rc<[String]> = script_args.into();
+        let mut times = Vec::with_capacity(runs);
+        for _ in 0..runs {
+            let mut env = Environment {
+                args: script_args.clone(),
+                max_depth: args.max_depth.unwrap_or(usize::MAX),
+                max_stack,
+                strict: program.strict,
+                ..Default::default()
+            };
+            let start = Instant::now();
+            let result = env.eval(program.clone());
+            times.push(start.elapsed());
+            match result {
+                // Exiting successfully just ends the run.
+                Err(Error {
+                    kind: ErrorKind::Exit(0),
+                    ..
+                }) => {}
+                Err(Error {
+                    kind: ErrorKind::Exit(code),
+                    ..
+                }) => std::process::exit(code),
+                Err(e) => {
+                    e.report(source);
+                    std::process::exit(EXIT_FAILURE);
+                }
+                Ok(Value::Error(error)) => {
+                    Error::from(Rc::unwrap_or_clone(error)).report(source);
+                    std::process::exit(EXIT_FAILURE);
+                }
+                Ok(_) => {}
+            }
+        }
+        times.sort();
+        if let (Some(min), Some(max)) = (times.first(), times.last()) {
+            // Programs are only ever run by the tree-walking evaluator, so there's one backend.
+            eprintln!(
+                "evaluator: {runs} runs, min {min:?}, median {:?}, mean {:?}, max {max:?}",
+                times[times.len() / 2],
+                times.iter().sum::<Duration>() / runs as u32,
+            );
+        }
+        return;
+    }
+
+    let input = if let Some(code) = args.eval {
+        // Without a file, every positional argument is for the code.
+        let script_args = args.file.map(|file| file.display().to_string());
+        args.args.splice(0..0, script_args);
+        Some((code, "<eval>".to_string()))
+    } else {
+        args.file.as_deref().map(read_or_exit)
+    };
+
+    if let Some((contents, name)) = input {
+        let source = Source {
+            name: &name,
+            text: &contents,
+        };
+        if let Some(emit) = args.emit {
+            if !self::emit(emit, args.format, source) {
+                std::process::exit(EXIT_FAILURE);
+            }
+            return;
+        }
+        let start = Instant::now();
+        let program = parse_or_exit(source);
+        let parse_time = start.elapsed();
+        if !lint(source, &program, args.strict, &args.allow) {
+            std::process::exit(EXIT_FAILURE);
+        }
+        let mut env = Environment {
+            args: args.args.into(),
+            max_depth: args.max_depth.unwrap_or(usize::MAX),
+            max_stack,
+            steps_remaining: args.max_steps,
+            strict: args.strict || program.strict,
+            ..Default::default()
+        };
+        let start = Instant::now();
+        let code = run(source, program, &mut env);
+        if args.time {
+            eprintln!("parse:   {parse_time:?}");
+            eprintln!(
+                "execute: {:?} ({} expressions, call depth {})",
+                start.elapsed(),
+                env.stats.steps,
+                env.stats.peak_depth,
+            );
+        }
+        if code != 0 {
+            std::process::exit(code);
         }
         return;
     }
@@ -27,23 +239,133 @@ fn main() {
     println!("Monkey REPL");
     println!("Ctrl-D to exit");
 
+    let config_dir = config::dir();
+    let config = config_dir.as_deref().map(config::load).unwrap_or_default();
+    if let (None, Some(color)) = (args.color, config.color) {
+        report::set_color(color);
+    }
+
     let mut rl = rustyline::DefaultEditor::new().unwrap();
-    let mut env = Environment::default();
+    // Ctrl-C at the prompt is handled by rustyline. While a line is being evaluated, it stops
+    // evaluating that line instead of ending the session.
+    let interrupt = Arc::new(AtomicBool::new(false));
+    let handler = interrupt.clone();
+    if let Err(err) = ctrlc::set_handler(move || handler.store(true, Ordering::Relaxed)) {
+        eprintln!("Warning: Ctrl-C will end the session: {err}");
+    }
+    let mut env = Environment {
+        max_depth: args.max_depth.unwrap_or(usize::MAX),
+        max_stack,
+        strict: args.strict,
+        interrupt: Some(interrupt.clone()),
+        ..Default::default()
+    };
+    // Definitions from the init script are available in every session.
+    if let Some(path) = config_dir.map(|dir| dir.join("init.mk"))
+        && let Some(contents) = config::read(&path)
+    {
+        let source = Source {
+            name: path.display().to_string().leak(),
+            text: contents.leak(),
+        };
+        repl_eval(&mut env, source);
+    }
+    // Inputs that were evaluated without errors, for `:save`.
+    let mut transcript = Vec::new();
+    for path in &args.load {
+        let (contents, name) = read_or_exit(path);
+        let contents = contents.leak();
+        let source = Source {
+            name: name.leak(),
+            text: contents,
+        };
+        if repl_eval(&mut env, source).is_some() {
+            transcript.push(contents.trim_end());
+        }
+    }
 
     loop {
-        match rl.readline(">> ") {
+        match rl.readline(&config.prompt) {
             Ok(line) => {
                 let _ = rl.add_history_entry(&line);
                 let line = line.leak().trim();
-                let program = parse_program(line).unwrap();
-                let value = match env.eval(program) {
-                    Ok(value) => value,
-                    Err(e) => {
-                        e.report(line);
-                        continue;
+                env.steps_remaining = args.max_steps;
+                interrupt.store(false, Ordering::Relaxed);
+                if let Some(command) = line.strip_prefix(':') {
+                    let (command, rest) = command.split_once(' ').unwrap_or((command, ""));
+                    match command {
+                        "type" => {
+                            if let Some(value) = repl_eval(&mut env, repl_source(rest.trim())) {
+                                match value {
+                                    Value::Function(function) => {
+                                        let parameters = function
+                                            .parameters
+                                            .iter()
+                                            .map(|parameter| parameter.name)
+                                            .collect::<Vec<_>>();
+                                        println!(
+                                            "{} fn({})",
+                                            Type::Function,
+                                            parameters.join(", ")
+                                        );
+                                    }
+                                    value => println!("{}", Type::from(&value)),
+                                }
+                            }
+                        }
+                        "ast" | "tokens" => {
+                            let emit = if command == "ast" {
+                                cli::Emit::Ast
+                            } else {
+                                cli::Emit::Tokens
+                            };
+                            let source = Source {
+                                name: "<repl>",
+                                text: rest.trim(),
+                            };
+                            self::emit(emit, args.format, source);
+                        }
+                        "save" if !rest.trim().is_empty() => {
+                            let path = rest.trim();
+                            let contents: String = transcript
+                                .iter()
+                                .map(|input| format!("{input}\n"))
+                                .collect();
+                            if let Err(err) = std::fs::write(path, contents) {
+                                eprintln!("Error: couldn't write {path}: {err}");
+                            }
+                        }
+                        "replay" if !rest.trim().is_empty() => {
+                            let path = rest.trim();
+                            let contents = match std::fs::read_to_string(path) {
+                                Ok(contents) => contents.leak(),
+                                Err(err) => {
+                                    eprintln!("Error: couldn't read {path}: {err}");
+                                    continue;
+                                }
+                            };
+                            let source = Source {
+                                name: path,
+                                text: contents,
+                            };
+                            if let Some(value) = repl_eval(&mut env, source) {
+                                transcript.push(contents.trim_end());
+                                print_inspected(&value);
+                            }
+                        }
+                        "save" | "replay" => eprintln!("usage: :{command} <file>"),
+                        "bytecode" => eprintln!(
+                            "there is no bytecode; programs are evaluated directly from the AST (see `:ast`)"
+                        ),
+                        _ => eprintln!("unknown command `:{command}`"),
                     }
+                    continue;
+                }
+                let Some(value) = repl_eval(&mut env, repl_source(line)) else {
+                    continue;
                 };
-                println!("{value}");
+                transcript.push(line);
+                print_inspected(&value);
             }
             Err(ReadlineError::Eof) => {
                 println!("Ctrl-D");
@@ -57,3 +379,165 @@ fn main() {
         }
     }
 }
+
+/// Prints the tokens or AST of `source`, reporting any errors. Returns whether there were none.
+fn emit(emit: cli::Emit, format: cli::AstFormat, source: Source) -> bool {
+    match emit {
+        cli::Emit::Tokens => match parse::tokenize(source.text) {
+            Ok(tokens) => {
+                for token in tokens {
+                    println!("{:?} {} {:?}", token.span, token.kind, token.text);
+                }
+                true
+            }
+            Err(e) => {
+                e.report(source);
+                false
+            }
+        },
+        cli::Emit::Ast => match parse_program(source.text) {
+            Ok(program) => {
+                match format {
+                    cli::AstFormat::Sexp => print!("{}", program.to_sexp()),
+                    cli::AstFormat::Source => print!("{program}"),
+                    cli::AstFormat::Tree => print!("{}", program.debug_tree()),
+                }
+                true
+            }
+            Err(errors) => {
+                for e in errors {
+                    e.report(source);
+                }
+                false
+            }
+        },
+    }
+}
+
+/// Sends logs to stderr, filtered by `MONKEY_LOG` if it's set and by `verbose` otherwise.
+fn init_logging(verbose: u8) {
+    let filter = std::env::var("MONKEY_LOG").unwrap_or_else(|_| {
+        match verbose {
+            0 => "warn",
+            1 => "debug",
+            _ => "trace",
+        }
+        .to_string()
+    });
+    tracing_subscriber::fmt()
+        .with_env_filter(tracing_subscriber::EnvFilter::new(filter))
+        .with_writer(std::io::stderr)
+        .with_ansi(report::color())
+        .without_time()
+        .init();
+}
+
+fn repl_source(line: &str) -> Source<'_> {
+    Source {
+        name: "<repl>",
+        text: line,
+    }
+}
+
+/// Prints a value the way it'd be written in source, over multiple lines if it's wide.
+fn print_inspected(value: &Value) {
+    let inspected = format!("{value:?}");
+    if inspected.len() > REPL_WIDTH {
+        println!("{value:#?}");
+    } else {
+        println!("{inspected}");
+    }
+}
+
+/// Evaluates a line of REPL input, reporting any errors. Error values are reported rather than
+/// returned.
+fn repl_eval<'a>(env: &mut Environment<'a>, source: Source<'a>) -> Option<Value<'a>> {
+    let program = match parse_program(source.text) {
+        Ok(program) => program,
+        Err(errors) => {
+            for e in errors {
+                e.report(source);
+            }
+            return None;
+        }
+    };
+    match env.eval(program) {
+        Ok(Value::Error(error)) => {
+            Error::from(Rc::unwrap_or_clone(error)).report(source);
+            None
+        }
+        Ok(value) => Some(value),
+        Err(Error {
+            kind: ErrorKind::Exit(code),
+            ..
+        }) => std::process::exit(code),
+        Err(e) => {
+            e.report(source);
+            None
+        }
+    }
+}
+
+/// Reads the file at `path`, or standard input if it's `-`, returning its contents and the name to
+/// report it under.
+fn read_or_exit(path: &Path) -> (String, String) {
+    let (contents, name) = if path.as_os_str() == "-" {
+        (
+            std::io::read_to_string(std::io::stdin()),
+            "<stdin>".to_string(),
+        )
+    } else {
+        (std::fs::read_to_string(path), path.display().to_string())
+    };
+    let contents = contents.unwrap_or_else(|err| {
+        eprintln!("Error: couldn't read {name}: {err}");
+        std::process::exit(EXIT_FAILURE);
+    });
+    (contents, name)
+}
+
+/// Reports the warnings in `program` that aren't allowed, as errors in strict mode. Returns whether
+/// the program can run, which it can't if there are any errors.
+fn lint(source: Source, program: &Program, strict: bool, allow: &[Lint]) -> bool {
+    let strict = strict || program.strict;
+    let mut warned = false;
+    for warning in lint::check(program) {
+        if !allow.contains(&Lint::from(&warning.kind)) {
+            warning.report(source, strict);
+            warned = true;
+        }
+    }
+    !(strict && warned)
+}
+
+fn parse_or_exit(source: Source<'_>) -> Program<'_> {
+    parse_program(source.text).unwrap_or_else(|errors| {
+        for e in errors {
+            e.report(source);
+        }
+        std::process::exit(EXIT_FAILURE);
+    })
+}
+
+/// Runs `program`, printing its result or reporting its error. Returns the status to exit with.
+fn run<'a>(source: Source<'a>, program: Program<'a>, env: &mut Environment<'a>) -> i32 {
+    match env.eval(program) {
+        Err(Error {
+            kind: ErrorKind::Exit(code),
+            ..
+        }) => code,
+        Err(e) => {
+            e.report(source);
+            EXIT_FAILURE
+        }
+        Ok(Value::Error(error)) => {
+            Error::from(Rc::unwrap_or_clone(error)).report(source);
+            EXIT_FAILURE
+        }
+        Ok(Value::Null) => 0,
+        Ok(value) => {
+            println!("{value}");
+            0
+        }
+    }
+}
@@ -1,11 +1,16 @@
+//! Turning source text into tokens and a [`Program`], with errors for what doesn't parse.
+
 pub use crate::ast::*;
+use crate::report::Source;
 use nom::{
-    IResult, Parser,
+    Input, Parser,
     branch::alt,
     bytes::complete::{is_not, tag, take_while, take_while_m_n},
-    character::complete::{char, digit1, line_ending, multispace0, multispace1, satisfy},
+    character::complete::{
+        char, digit1, line_ending, multispace0, multispace1, not_line_ending, satisfy, space0,
+    },
     combinator::{eof, opt, peek, recognize, value, verify},
-    multi::{fold, separated_list0},
+    multi::{fold, many0_count, separated_list0},
     sequence::{delimited, preceded, separated_pair, terminated},
 };
 use nom_locate::LocatedSpan;
@@ -13,6 +18,108 @@ use nom_tracable::{TracableInfo, tracable_parser};
 
 type InputSpan<'a> = LocatedSpan<&'a str, TracableInfo>;
 
+type IResult<I, O> = nom::IResult<I, O, Error>;
+
+#[derive(thiserror::Error, Debug, Clone)]
+#[error("{kind}")]
+pub struct Error {
+    pub span: Span,
+    pub kind: ErrorKind,
+    /// Advice that depends on what was being parsed when the error occurred.
+    pub help: Option<&'static str>,
+}
+
+#[derive(thiserror::Error, Debug, Clone)]
+pub enum ErrorKind {
+    #[error("unexpected end of input")]
+    UnexpectedEof,
+    #[error("unexpected `{0}`")]
+    Unexpected(char),
+    #[error("unrecognized character `{0}`")]
+    UnrecognizedChar(char),
+    #[error("unterminated string literal")]
+    UnterminatedString,
+    #[error("integer literal exceeds the maximum value {}", i64::MAX)]
+    IntegerOverflow,
+    #[error("missing `;` after this expression")]
+    MissingSemicolon,
+}
+
+impl Error {
+    pub fn new(span: Span, kind: ErrorKind) -> Self {
+        Self {
+            span,
+            kind,
+            help: None,
+        }
+    }
+
+    /// An error for whatever comes next in `input`, after any whitespace.
+    fn unexpected(input: &InputSpan) -> Self {
+        let rest = input.trim_start();
+        let start = input.location_offset() + (input.len() - rest.len());
+        match rest.chars().next() {
+            Some(c) => Self::new(
+                (start..start + c.len_utf8()).into(),
+                if can_start_token(c) {
+                    ErrorKind::Unexpected(c)
+                } else {
+                    ErrorKind::UnrecognizedChar(c)
+                },
+            ),
+            None => Self::new((start..start).into(), ErrorKind::UnexpectedEof),
+        }
+    }
+
+    pub fn report(&self, source: Source) {
+        self.write_report(source, std::io::stderr());
+    }
+
+    /// Like [`report`](Self::report), but writes to `out` instead of stderr.
+    pub fn write_report(&self, source: Source, out: impl std::io::Write) {
+        use ariadne::{Color, Label, ReportKind};
+
+        let mut builder = source
+            .build_report(ReportKind::Error, self.span)
+            .with_message(&self.kind)
+            .with_label(Label::new(source.span(self.span)).with_color(Color::Red));
+        if let Some(help) = self.help {
+            builder = builder.with_help(help);
+        }
+        source.write(builder, out);
+    }
+}
+
+/// Whether `c` can begin some piece of valid syntax.
+fn can_start_token(c: char) -> bool {
+    unicode_ident::is_xid_start(c) || c.is_ascii_digit() || "\"()[]{},;:=!+-*/<>".contains(c)
+}
+
+impl nom::error::ParseError<InputSpan<'_>> for Error {
+    fn from_error_kind(input: InputSpan, _kind: nom::error::ErrorKind) -> Self {
+        Self::unexpected(&input)
+    }
+
+    fn append(_input: InputSpan, _kind: nom::error::ErrorKind, other: Self) -> Self {
+        other
+    }
+
+    /// Keeps whichever alternative got further, which is most likely the one that was meant.
+    fn or(self, other: Self) -> Self {
+        if other.span.start > self.span.start {
+            other
+        } else {
+            self
+        }
+    }
+}
+
+impl<E> nom::error::FromExternalError<InputSpan<'_>, E> for Error {
+    fn from_external_error(input: InputSpan, _kind: nom::error::ErrorKind, _e: E) -> Self {
+        Self::unexpected(&input)
+    }
+}
+
 impl Spanned for InputSpan<'_> {
     fn span(&self) -> Span {
         (self.location_offset()..(self.location_offset() + self.len())).into()
@@ -29,53 +136,404 @@ fn spanned_tag<
     nom::bytes::complete::tag(tag).map(|v| Spanned::span(&v))
 }
 
-fn surround_ws<I: Clone + nom::Input, E: nom::error::ParseError<I>, O>(
-    f: impl Parser<I, Output = O, Error = E>,
-) -> impl Parser<I, Output = O, Error = E>
-where
-    I::Item: nom::AsChar,
-{
-    delimited(multispace0, f, multispace0)
+fn surround_ws<'a, O>(
+    f: impl Parser<InputSpan<'a>, Output = O, Error = Error>,
+) -> impl Parser<InputSpan<'a>, Output = O, Error = Error> {
+    delimited(ws, f, ws)
+}
+
+/// Attaches `help` to any error from `parser`, except for an unrecognized character, which isn't
+/// fixed by following the help.
+fn with_help<'a, O>(
+    help: &'static str,
+    mut parser: impl Parser<InputSpan<'a>, Output = O, Error = Error>,
+) -> impl Parser<InputSpan<'a>, Output = O, Error = Error> {
+    move |input| {
+        parser.parse(input).map_err(|e| {
+            e.map(|e| match e.kind {
+                ErrorKind::UnrecognizedChar(_) => e,
+                _ => Error {
+                    help: Some(help),
+                    ..e
+                },
+            })
+        })
+    }
 }
 
 /// Comma-separated list with optional trailing comma and surrounding whitespace
-fn csl<I: Clone + nom::Input, E: nom::error::ParseError<I>, F: Parser<I, Error = E>>(
+fn csl<'a, F: Parser<InputSpan<'a>, Error = Error>>(
     f: F,
-) -> impl Parser<I, Output = Vec<F::Output>, Error = E>
-where
-    I::Item: nom::AsChar,
-{
+) -> impl Parser<InputSpan<'a>, Output = Vec<F::Output>, Error = Error> {
     terminated(
         separated_list0(surround_ws(char(',')), f),
         opt(surround_ws(char(','))),
     )
 }
 
-pub fn parse_program(input: &str) -> Result<Program<'_>, nom::Err<nom::error::Error<&str>>> {
-    parse_statements(InputSpan::new_extra(input, TracableInfo::default()))
-        .map(|(_, statements)| Program { statements })
-        .map_err(|e| e.map_input(InputSpan::into_fragment))
+/// Skips any whitespace and comments.
+#[tracable_parser]
+fn ws(input: InputSpan) -> IResult<InputSpan, ()> {
+    value((), many0_count(alt((multispace1, parse_comment)))).parse(input)
 }
 
+/// A `//` comment, up to but not including the end of the line.
+#[tracable_parser]
+fn parse_comment(input: InputSpan) -> IResult<InputSpan, InputSpan> {
+    recognize((tag("//"), not_line_ending)).parse(input)
+}
+
+/// Parses a whole program, recovering from errors to report as many of them as possible.
+pub fn parse_program(source: &str) -> Result<Program<'_>, Vec<Error>> {
+    let mut input = InputSpan::new_extra(source, TracableInfo::default());
+    let mut statements = Vec::new();
+    loop {
+        let (rest, mut parsed) = parse_statements(input).map_err(|e| vec![into_error(e)])?;
+        statements.append(&mut parsed);
+        // A statement list only stops early at a `}` it didn't open.
+        let rest = rest.take_from(rest.len() - rest.trim_start().len());
+        if rest.is_empty() {
+            break;
+        }
+        statements.push(Statement::Error(Error::unexpected(&rest)));
+        input = rest.take_from(1);
+    }
+
+    let mut errors = Vec::new();
+    collect_errors(&statements, &mut errors);
+    // Wherever parsing ran out of input, point just past the last token rather than at any
+    // whitespace or comments after it.
+    let end = tokenize(source)
+        .ok()
+        .and_then(|tokens| {
+            tokens
+                .iter()
+                .rfind(|token| !matches!(token.kind, TokenKind::Comment))
+                .map(|token| token.span.end)
+        })
+        .unwrap_or(source.trim_end().len());
+    for error in &mut errors {
+        if let ErrorKind::UnexpectedEof = error.kind {
+            error.span = (end..end).into();
+        }
+    }
+    tracing::debug!(
+        statements = statements.len(),
+        errors = errors.len(),
+        "parsed program"
+    );
+    if errors.is_empty() {
+        let comments = tokenize(source)
+            .into_iter()
+            .flatten()
+            .filter(|token| matches!(token.kind, TokenKind::Comment))
+            .map(|token| Comment {
+                span: token.span,
+                text: token.text,
+            })
+            .collect();
+        Ok(Program::new(statements, comments))
+    } else {
+        Err(errors)
+    }
+}
+
+fn into_error(e: nom::Err<Error>) -> Error {
+    match e {
+        nom::Err::Error(e) | nom::Err::Failure(e) => e,
+        nom::Err::Incomplete(_) => unreachable!("only complete parsers are used"),
+    }
+}
+
+/// Collects the errors recovered from within `statements`, in source order.
+///
+/// Expressions can nest arbitrarily deeply, so this walks them with a stack instead of
+/// recursing. Everything is pushed in reverse so that it's popped in source order.
+fn collect_errors(statements: &[Statement], errors: &mut Vec<Error>) {
+    enum Item<'s, 'a> {
+        Statement(&'s Statement<'a>),
+        Expression(&'s Expression<'a>),
+    }
+
+    let mut stack: Vec<_> = statements.iter().rev().map(Item::Statement).collect();
+    while let Some(item) = stack.pop() {
+        let expression = match item {
+            Item::Statement(Statement::Error(error)) => {
+                errors.push(error.clone());
+                continue;
+            }
+            Item::Statement(
+                Statement::Let { value, .. }
+                | Statement::Return { value, .. }
+                | Statement::Expression { value, .. },
+            ) => value,
+            Item::Expression(expression) => expression,
+        };
+        match expression {
+            Expression::Error(error) => errors.push(error.clone()),
+            Expression::Prefix { right, .. } => stack.push(Item::Expression(right)),
+            Expression::Infix { left, right, .. } => {
+                stack.push(Item::Expression(right));
+                stack.push(Item::Expression(left));
+            }
+            Expression::If {
+                condition,
+                consequence,
+                alternative,
+                ..
+            } => {
+                if let Some(alternative) = alternative {
+                    stack.extend(alternative.statements.iter().rev().map(Item::Statement));
+                }
+                stack.extend(consequence.statements.iter().rev().map(Item::Statement));
+                stack.push(Item::Expression(condition));
+            }
+            Expression::Function { body, .. } => {
+                stack.extend(body.statements.iter().rev().map(Item::Statement));
+            }
+            Expression::Call {
+                function,
+                arguments,
+                ..
+            } => {
+                stack.extend(arguments.iter().rev().map(Item::Expression));
+                stack.push(Item::Expression(function));
+            }
+            Expression::Array { elements, .. } => {
+                stack.extend(elements.iter().rev().map(Item::Expression));
+            }
+            Expression::Index {
+                collection, index, ..
+            } => {
+                stack.push(Item::Expression(index));
+                stack.push(Item::Expression(collection));
+            }
+            Expression::Map { elements, .. } => {
+                for (key, value) in elements.iter().rev() {
+                    stack.push(Item::Expression(value));
+                    stack.push(Item::Expression(key));
+                }
+            }
+            Expression::Identifier(_)
+            | Expression::Integer { .. }
+            | Expression::Boolean { .. }
+            | Expression::Null(_)
+            | Expression::String { .. } => {}
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, strum::Display)]
+#[strum(serialize_all = "lowercase")]
+pub enum TokenKind {
+    Keyword,
+    Identifier,
+    Integer,
+    String,
+    Operator,
+    Punctuation,
+    Comment,
+}
+
+pub struct Token<'a> {
+    pub kind: TokenKind,
+    pub text: &'a str,
+    pub span: Span,
+}
+
+/// Splits `input` into tokens using the same pieces the parser is built from. The parser works on
+/// characters directly, so this is only for inspecting how it sees the source.
+pub fn tokenize(input: &str) -> Result<Vec<Token<'_>>, Error> {
+    let mut input = InputSpan::new_extra(input, TracableInfo::default());
+    let mut tokens = Vec::new();
+    loop {
+        (input, _) = multispace0::<_, Error>(input).map_err(into_error)?;
+        if input.is_empty() {
+            return Ok(tokens);
+        }
+        let (rest, kind) = alt((
+            value(TokenKind::Comment, parse_comment),
+            parse_word.map(|word| {
+                if KEYWORDS.contains(word.fragment()) {
+                    TokenKind::Keyword
+                } else {
+                    TokenKind::Identifier
+                }
+            }),
+            value(TokenKind::Integer, digit1),
+            value(TokenKind::String, parse_string),
+            value(TokenKind::Operator, parse_infix_operator),
+            value(TokenKind::Operator, parse_prefix_operator),
+            value(TokenKind::Operator, char('=')),
+            value(TokenKind::Punctuation, satisfy(|c| "()[]{},;:".contains(c))),
+        ))
+        .parse(input)
+        .map_err(into_error)?;
+        let len = input.len() - rest.len();
+        tokens.push(Token {
+            kind,
+            text: &input.fragment()[..len],
+            span: input.take(len).span(),
+        });
+        input = rest;
+    }
+}
+
+/// Parses statements up to the end of the input or an unmatched `}`. Statements that fail to
+/// parse are kept as [`Statement::Error`]s, and parsing resumes after them.
 #[tracable_parser]
 fn parse_statements(input: InputSpan) -> IResult<InputSpan, Vec<Statement>> {
-    separated_list0(multispace0, parse_statement).parse(input)
+    let mut input = input;
+    let mut statements = Vec::new();
+    loop {
+        (input, _) = ws(input)?;
+        if input.is_empty() || input.starts_with('}') {
+            return Ok((input, statements));
+        }
+        match terminated(parse_statement, parse_terminator).parse(input) {
+            Ok((rest, statement)) => {
+                statements.push(statement);
+                input = rest;
+            }
+            // Two statements on one line is most likely a missing `;`, so carry on as if it were
+            // there rather than skipping the second statement.
+            Err(nom::Err::Error(_))
+                if let Some((rest, statement, error)) = missing_semicolon(input) =>
+            {
+                statements.push(statement);
+                statements.push(Statement::Error(error));
+                input = rest;
+            }
+            Err(nom::Err::Error(e) | nom::Err::Failure(e)) => {
+                statements.push(Statement::Error(e));
+                input = skip_statement(input);
+            }
+            Err(e) => return Err(e),
+        }
+    }
 }
 
+/// Skips the rest of a statement that failed to parse: through the next `;` or newline outside of
+/// any brackets or strings, or up to a `}` closing a block the statement is in.
+fn skip_statement(input: InputSpan) -> InputSpan {
+    let rest = skip_to(input, |c| matches!(c, ';' | '\n' | '}'));
+    if rest.starts_with([';', '\n']) {
+        rest.take_from(1)
+    } else {
+        rest
+    }
+}
+
+/// Recovers from `err` in an expression inside brackets closed by `close`, by skipping to that
+/// bracket, or with `commas`, to a `,` before the next element. The error is kept as an
+/// [`Expression::Error`] in place of what failed to parse, so that the rest of the brackets can
+/// still be checked. If a `;` or another closing bracket comes first, the brackets are most likely
+/// unclosed, so `err` is returned as it is.
+fn recover_element<'a>(
+    input: InputSpan<'a>,
+    close: char,
+    commas: bool,
+    err: nom::Err<Error>,
+) -> IResult<InputSpan<'a>, Expression<'a>> {
+    let rest = skip_to(input, |c| {
+        c == close || (commas && c == ',') || matches!(c, ';' | ')' | ']' | '}')
+    });
+    match &err {
+        nom::Err::Error(error) | nom::Err::Failure(error)
+            if rest.starts_with(close) || (commas && rest.starts_with(',')) =>
+        {
+            Ok((rest, Expression::Error(error.clone())))
+        }
+        _ => Err(err),
+    }
+}
+
+/// Skips to the first character outside of any brackets, strings, or comments that `stop` is
+/// true for, or to the end of the input.
+fn skip_to(input: InputSpan, stop: impl Fn(char) -> bool) -> InputSpan {
+    let mut depth = 0usize;
+    let mut chars = input.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            _ if depth == 0 && stop(c) => return input.take_from(i),
+            '"' => {
+                // Skip to the closing quote, ignoring escaped ones.
+                while let Some((_, c)) = chars.next() {
+                    match c {
+                        '\\' => {
+                            chars.next();
+                        }
+                        '"' => break,
+                        _ => {}
+                    }
+                }
+            }
+            '/' if input.fragment()[i + 1..].starts_with('/') => {
+                // Skip the comment, but not the newline ending it.
+                while chars.next_if(|&(_, c)| c != '\n').is_some() {}
+            }
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    input.take_from(input.len())
+}
+
+/// A statement without its terminator.
 #[tracable_parser]
 fn parse_statement(input: InputSpan) -> IResult<InputSpan, Statement> {
-    terminated(
-        alt((
-            parse_return,
-            parse_let,
-            (parse_expression, opt(peek(char(';'))).map(|v| v.is_some()))
-                .map(|(value, semi)| Statement::Expression { value, semi }),
-        )),
-        alt((tag(";"), line_ending, eof)),
+    alt((
+        parse_return,
+        parse_let,
+        (
+            parse_expression,
+            opt(peek((space0, char(';')))).map(|v| v.is_some()),
+        )
+            .map(|(value, semi)| Statement::Expression { value, semi }),
+    ))
+    .parse(input)
+}
+
+#[tracable_parser]
+fn parse_terminator(input: InputSpan) -> IResult<InputSpan, InputSpan> {
+    with_help(
+        "statements must end with `;` or a newline",
+        preceded(
+            (space0, opt(parse_comment)),
+            // The last statement in a block can end at its closing brace.
+            alt((tag(";"), line_ending, eof, peek(tag("}")))),
+        ),
     )
     .parse(input)
 }
 
+/// If the statement at the start of `input` is followed on the same line by a statement keyword,
+/// parses it as though it ended with `;`, returning the input after it, the statement, and an error
+/// at its last character.
+fn missing_semicolon(input: InputSpan) -> Option<(InputSpan, Statement, Error)> {
+    let (rest, mut statement) = parse_statement(input).ok()?;
+    let (next, _) = space0::<_, Error>(rest).ok()?;
+    let (_, word) = parse_word(next).ok()?;
+    if !["let", "return"].contains(word.fragment()) {
+        return None;
+    }
+    if let Statement::Expression { semi, .. } = &mut statement {
+        *semi = true;
+    }
+    let end = statement.span().end;
+    let last = input.fragment()[..end - input.location_offset()]
+        .chars()
+        .next_back()
+        .map_or(0, char::len_utf8);
+    let error = Error {
+        span: (end - last..end).into(),
+        kind: ErrorKind::MissingSemicolon,
+        help: Some("add `;` or a newline to end the statement"),
+    };
+    Some((next, statement, error))
+}
+
 #[tracable_parser]
 fn parse_let(input: InputSpan) -> IResult<InputSpan, Statement> {
     (
@@ -96,79 +554,172 @@ fn parse_expression(input: InputSpan) -> IResult<InputSpan, Expression> {
     parse_expression_inner(input, 0)
 }
 
-fn parse_expression_inner(input: InputSpan, min_precedence: u8) -> IResult<InputSpan, Expression> {
-    let (mut input, mut lhs) = alt((
-        parse_boolean,
-        parse_null,
-        parse_function,
-        parse_if,
-        parse_identifier.map(Expression::Identifier),
-        parse_grouped,
-        parse_integer,
-        parse_prefix,
-        parse_string,
-        parse_array,
-        parse_map,
-    ))
-    .parse(input)?;
+/// Parses an expression whose infix operators all bind at least as tightly as `min_precedence`.
+///
+/// Prefix operators, groups, and the right-hand sides of infix operators can nest arbitrarily
+/// deeply, so instead of recursing into them, this keeps what it's in the middle of on a stack of
+/// frames, and finishes each one once the expression inside it ends. An error inside parentheses
+/// is kept as an [`Expression::Error`] in place of the group, and parsing carries on after it.
+fn parse_expression_inner(
+    mut input: InputSpan,
+    mut min_precedence: u8,
+) -> IResult<InputSpan, Expression> {
+    /// Something waiting on the expression being parsed, with the `min_precedence` to go back to
+    /// once it's finished.
+    enum Frame<'a> {
+        /// A prefix operator, which applies to everything after it.
+        Prefix(Prefix, u8),
+        /// An opening parenthesis, to be matched by a closing one.
+        Group(u8),
+        /// The left-hand side and operator of an infix expression.
+        Infix(Expression<'a>, InfixOperator, u8),
+    }
 
-    loop {
-        if let Ok((next_input, (arguments, close_span))) = parse_call_args(input) {
-            lhs = Expression::Call {
-                function: Box::new(lhs),
-                arguments,
-                close_span,
-            };
-            input = next_input;
-            continue;
-        }
+    let mut frames = Vec::new();
+    // What's left of a group that failed to parse, to carry on from instead of an operand.
+    let mut recovered = None;
 
-        if let Ok((next_input, (index, close_span))) = parse_index(input) {
-            lhs = Expression::Index {
-                collection: Box::new(lhs),
-                index,
-                close_span,
-            };
-            input = next_input;
-            continue;
-        }
+    /// Unwraps `result`, or recovers from its error by skipping to the end of the innermost group
+    /// and continuing `label`, the loop over operands.
+    macro_rules! recover {
+        ($label:lifetime, $result:expr) => {
+            match $result {
+                Ok(output) => output,
+                Err(err) => {
+                    let Some(group) = frames
+                        .iter()
+                        .rposition(|frame| matches!(frame, Frame::Group(_)))
+                    else {
+                        return Err(err);
+                    };
+                    let (rest, error) = recover_element(input, ')', false, err)?;
+                    let Some(Frame::Group(outer)) = frames.drain(group..).next() else {
+                        unreachable!("the frame was found to be a group")
+                    };
+                    recovered = Some(error);
+                    input = rest.take_from(1);
+                    min_precedence = outer;
+                    continue $label;
+                }
+            }
+        };
+    }
 
-        let Ok((next_input, operator)) =
-            delimited(multispace0, parse_infix_operator, multispace0).parse(input)
-        else {
-            break;
+    'expression: loop {
+        let mut lhs = if let Some(error) = recovered.take() {
+            error
+        } else {
+            loop {
+                if let Ok((next_input, _)) = char::<_, Error>('(').parse(input) {
+                    frames.push(Frame::Group(min_precedence));
+                    input = next_input;
+                } else if let Ok((next_input, prefix)) = parse_prefix_operator(input) {
+                    frames.push(Frame::Prefix(prefix, min_precedence));
+                    input = next_input;
+                } else {
+                    break;
+                }
+                min_precedence = 0;
+            }
+
+            let operand = alt((
+                parse_boolean,
+                parse_null,
+                parse_function,
+                parse_if,
+                parse_identifier.map(Expression::Identifier),
+                parse_integer,
+                parse_string,
+                parse_array,
+                parse_map,
+            ))
+            .parse(input);
+            let (next_input, lhs) = recover!('expression, operand);
+            input = next_input;
+            lhs
         };
 
-        let (lp, rp) = operator.precedence();
+        loop {
+            // A bracket right after an expression can only be a call or an index, so errors inside
+            // them are reported rather than ending the expression early.
+            if input.starts_with('(') {
+                let (next_input, (arguments, close_span)) =
+                    recover!('expression, parse_call_args(input));
+                lhs = Expression::Call {
+                    function: Box::new(lhs),
+                    arguments,
+                    close_span,
+                };
+                input = next_input;
+                continue;
+            }
 
-        if lp < min_precedence {
-            break;
-        }
+            if input.starts_with('[') {
+                let (next_input, (index, close_span)) = recover!('expression, parse_index(input));
+                lhs = Expression::Index {
+                    collection: Box::new(lhs),
+                    index,
+                    close_span,
+                };
+                input = next_input;
+                continue;
+            }
 
-        let (next_input, rhs) = parse_expression_inner(next_input, rp)?;
+            if let Ok((next_input, operator)) = delimited(ws, parse_infix_operator, ws).parse(input)
+            {
+                let (lp, rp) = operator.precedence();
+                if lp >= min_precedence {
+                    frames.push(Frame::Infix(lhs, operator, min_precedence));
+                    min_precedence = rp;
+                    input = next_input;
+                    break;
+                }
+            }
 
-        lhs = Expression::Infix {
-            left: Box::new(lhs),
-            operator,
-            right: Box::new(rhs),
-        };
-        input = next_input;
+            // Nothing more belongs to this expression, so finish whatever it was inside of.
+            if let Some(Frame::Group(_)) = frames.last() {
+                (input, _) = recover!('expression, char(')').parse(input));
+            }
+            match frames.pop() {
+                None => return Ok((input, lhs)),
+                Some(Frame::Prefix(prefix, outer)) => {
+                    lhs = Expression::Prefix {
+                        prefix,
+                        right: Box::new(lhs),
+                    };
+                    min_precedence = outer;
+                }
+                Some(Frame::Group(outer)) => min_precedence = outer,
+                Some(Frame::Infix(left, operator, outer)) => {
+                    lhs = Expression::Infix {
+                        left: Box::new(left),
+                        operator,
+                        right: Box::new(lhs),
+                    };
+                    min_precedence = outer;
+                }
+            }
+        }
     }
-
-    Ok((input, lhs))
 }
 
-#[tracable_parser]
-fn parse_grouped(input: InputSpan) -> IResult<InputSpan, Expression> {
-    delimited(char('('), parse_expression, char(')')).parse(input)
-}
+const KEYWORDS: &[&str] = &["let", "return", "fn", "if", "else", "true", "false", "null"];
 
+/// An identifier or keyword.
 #[tracable_parser]
-fn parse_identifier(input: InputSpan) -> IResult<InputSpan, Identifier> {
+fn parse_word(input: InputSpan) -> IResult<InputSpan, InputSpan> {
     recognize((
         satisfy(unicode_ident::is_xid_start),
         take_while(unicode_ident::is_xid_continue),
     ))
+    .parse(input)
+}
+
+#[tracable_parser]
+fn parse_identifier(input: InputSpan) -> IResult<InputSpan, Identifier> {
+    verify(parse_word, |name: &InputSpan| {
+        !KEYWORDS.contains(name.fragment())
+    })
     .map(|value| Identifier {
         span: Spanned::span(&value),
         name: InputSpan::into_fragment(value),
@@ -178,28 +729,27 @@ fn parse_identifier(input: InputSpan) -> IResult<InputSpan, Identifier> {
 
 #[tracable_parser]
 fn parse_return(input: InputSpan) -> IResult<InputSpan, Statement> {
-    separated_pair(spanned_tag("return"), multispace0, parse_expression)
+    separated_pair(spanned_tag("return"), ws, parse_expression)
         .map(|(return_span, value)| Statement::Return { return_span, value })
         .parse(input)
 }
 
 #[tracable_parser]
 fn parse_integer(input: InputSpan) -> IResult<InputSpan, Expression> {
-    digit1
-        .map_res(|digits: InputSpan| {
-            digits.parse().map(|value| Expression::Integer {
+    let (input, digits) = digit1(input)?;
+    match digits.parse() {
+        Ok(value) => Ok((
+            input,
+            Expression::Integer {
                 span: digits.span(),
                 value,
-            })
-        })
-        .parse(input)
-}
-
-#[tracable_parser]
-fn parse_prefix(input: InputSpan) -> IResult<InputSpan, Expression> {
-    (parse_prefix_operator, parse_expression.map(Box::new))
-        .map(|(prefix, right)| Expression::Prefix { prefix, right })
-        .parse(input)
+            },
+        )),
+        Err(_) => Err(nom::Err::Failure(Error::new(
+            digits.span(),
+            ErrorKind::IntegerOverflow,
+        ))),
+    }
 }
 
 #[tracable_parser]
@@ -245,15 +795,14 @@ fn parse_if(input: InputSpan) -> IResult<InputSpan, Expression> {
     (
         spanned_tag("if"),
         delimited(
-            multispace0,
+            ws,
             delimited(char('('), parse_expression.map(Box::new), char(')')),
-            multispace0,
+            ws,
         ),
         parse_block,
-        preceded(
-            multispace0,
-            opt(preceded((tag("else"), multispace0), parse_block)),
-        ),
+        // Whitespace after the consequence is only part of the `if` when an `else` follows, so
+        // that a newline can still end the statement.
+        opt(preceded((ws, tag("else"), ws), parse_block)),
     )
         .map(
             |(if_span, condition, consequence, alternative)| Expression::If {
@@ -270,7 +819,7 @@ fn parse_if(input: InputSpan) -> IResult<InputSpan, Expression> {
 fn parse_block(input: InputSpan) -> IResult<InputSpan, Block> {
     (
         spanned_tag("{"),
-        delimited(multispace0, parse_statements, multispace0),
+        delimited(ws, parse_statements, ws),
         spanned_tag("}"),
     )
         .map(|(open_span, statements, close_span)| Block {
@@ -285,12 +834,8 @@ fn parse_block(input: InputSpan) -> IResult<InputSpan, Block> {
 fn parse_function(input: InputSpan) -> IResult<InputSpan, Expression> {
     (
         spanned_tag("fn"),
-        delimited(
-            (char('('), multispace0),
-            csl(parse_identifier),
-            (char(')'), multispace0),
-        ),
-        multispace0,
+        delimited((char('('), ws), csl(parse_identifier), (char(')'), ws)),
+        ws,
         parse_block,
     )
         .map(|(fn_span, parameters, _, body)| Expression::Function {
@@ -303,7 +848,39 @@ fn parse_function(input: InputSpan) -> IResult<InputSpan, Expression> {
 
 #[tracable_parser]
 fn parse_call_args(input: InputSpan) -> IResult<InputSpan, (Vec<Expression>, Span)> {
-    (preceded(char('('), csl(parse_expression)), spanned_tag(")")).parse(input)
+    let (input, _) = char('(').parse(input)?;
+    parse_list(input, ')')
+}
+
+/// The rest of a comma-separated list of expressions after its opening bracket, with an optional
+/// trailing comma, and the span of the `close`ing bracket. Elements that fail to parse are kept as
+/// errors, so every mistake in the list is reported.
+fn parse_list(mut input: InputSpan, close: char) -> IResult<InputSpan, (Vec<Expression>, Span)> {
+    let mut elements = Vec::new();
+    loop {
+        if let Ok((rest, bracket)) = recognize(char::<_, Error>(close)).parse(input) {
+            return Ok((rest, (elements, bracket.span())));
+        }
+
+        let (rest, element) =
+            parse_expression(input).or_else(|err| recover_element(input, close, true, err))?;
+        elements.push(element);
+        input = rest;
+
+        if input.starts_with(close) {
+            continue;
+        }
+        if surround_ws(char(',')).parse(input).is_err() {
+            // Neither a comma nor the end of the list follows the element.
+            let err = nom::Err::Error(Error::unexpected(&input));
+            let (rest, error) = recover_element(input, close, true, err)?;
+            elements.push(error);
+            input = rest;
+        }
+        if let Ok((rest, _)) = surround_ws(char(',')).parse(input) {
+            input = rest;
+        }
+    }
 }
 
 #[tracable_parser]
@@ -313,23 +890,31 @@ fn parse_null(input: InputSpan) -> IResult<InputSpan, Expression> {
 
 #[tracable_parser]
 fn parse_string(input: InputSpan) -> IResult<InputSpan, Expression> {
-    (
-        spanned_tag("\""),
-        fold(0.., parse_fragment, String::new, |mut string, fragment| {
-            match fragment {
-                StringFragment::Literal(s) => string += s,
-                StringFragment::EscapedChar(c) => string.push(c),
-                StringFragment::EscapedWS => {}
-            }
-            string
-        }),
-        spanned_tag("\""),
-    )
-        .map(|(open, value, close)| Expression::String {
+    let (input, open) = spanned_tag("\"").parse(input)?;
+    let (input, value) = fold(0.., parse_fragment, String::new, |mut string, fragment| {
+        match fragment {
+            StringFragment::Literal(s) => string += s,
+            StringFragment::EscapedChar(c) => string.push(c),
+            StringFragment::EscapedWS => {}
+        }
+        string
+    })
+    .parse(input)?;
+    // A backslash at the end of the input would escape the closing quote if there were one.
+    if input.is_empty() || *input.fragment() == "\\" {
+        return Err(nom::Err::Failure(Error::new(
+            open,
+            ErrorKind::UnterminatedString,
+        )));
+    }
+    let (input, close) = spanned_tag("\"").parse(input)?;
+    Ok((
+        input,
+        Expression::String {
             span: open.join(close),
             value,
-        })
-        .parse(input)
+        },
+    ))
 }
 
 #[derive(Clone)]
@@ -394,22 +979,29 @@ fn parse_escaped_whitespace(input: InputSpan) -> IResult<InputSpan, InputSpan> {
 
 #[tracable_parser]
 fn parse_array(input: InputSpan) -> IResult<InputSpan, Expression> {
-    (spanned_tag("["), csl(parse_expression), spanned_tag("]"))
-        .map(|(open_span, elements, close_span)| Expression::Array {
+    let (input, open_span) = spanned_tag("[").parse(input)?;
+    let (input, (elements, close_span)) = parse_list(input, ']')?;
+    Ok((
+        input,
+        Expression::Array {
             open_span,
             elements,
             close_span,
-        })
-        .parse(input)
+        },
+    ))
 }
 
 #[tracable_parser]
 fn parse_index(input: InputSpan) -> IResult<InputSpan, (Box<Expression>, Span)> {
-    (
-        preceded(char('['), parse_expression).map(Box::new),
-        spanned_tag("]"),
-    )
-        .parse(input)
+    let (input, _) = char('[').parse(input)?;
+    let (mut input, mut index) =
+        parse_expression(input).or_else(|err| recover_element(input, ']', false, err))?;
+    if !input.starts_with(']') {
+        let err = nom::Err::Error(Error::unexpected(&input));
+        (input, index) = recover_element(input, ']', false, err)?;
+    }
+    let (input, close_span) = spanned_tag("]").parse(input)?;
+    Ok((input, (Box::new(index), close_span)))
 }
 
 #[tracable_parser]
@@ -421,7 +1013,7 @@ fn parse_map(input: InputSpan) -> IResult<InputSpan, Expression> {
             surround_ws(char(':')),
             parse_expression,
         )),
-        surround_ws(spanned_tag("}")),
+        preceded(ws, spanned_tag("}")),
     )
         .map(|(open_span, elements, close_span)| Expression::Map {
             open_span,
@@ -430,3 +1022,186 @@ fn parse_map(input: InputSpan) -> IResult<InputSpan, Expression> {
         })
         .parse(input)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A sum of `terms` ones, like code generators produce.
+    fn long_sum(terms: usize) -> String {
+        vec!["1"; terms].join(" + ")
+    }
+
+    #[test]
+    fn long_sum_parses() {
+        let source = long_sum(100_000);
+        let program = parse_program(&source).unwrap();
+        assert_eq!(program.statements[0].span().end, source.len());
+        assert!(crate::lint::check(&program).is_empty());
+    }
+
+    #[test]
+    fn long_sum_error_is_reported() {
+        let source = format!("{} @", long_sum(100_000));
+        let errors = parse_program(&source).unwrap_err();
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn deeply_nested_groups_parse() {
+        let source = format!("{}1{}", "(".repeat(2_000), ")".repeat(2_000));
+        assert!(parse_program(&source).is_ok());
+    }
+
+    #[test]
+    fn deeply_nested_prefixes_parse() {
+        let source = format!("{}1", "-".repeat(10_000));
+        assert!(parse_program(&source).is_ok());
+    }
+
+    #[test]
+    fn unclosed_group_is_an_error() {
+        assert!(parse_program("((1 + 2)").is_err());
+    }
+
+    /// The start of each error in `source`, which must fail to parse.
+    fn error_starts(source: &str) -> Vec<usize> {
+        let mut starts: Vec<_> = parse_program(source)
+            .unwrap_err()
+            .iter()
+            .map(|error| error.span.start)
+            .collect();
+        starts.sort();
+        starts
+    }
+
+    #[test]
+    fn every_bad_array_element_is_reported() {
+        assert_eq!(error_starts("let a = [1, @, 3, %];"), [12, 18]);
+    }
+
+    #[test]
+    fn every_bad_argument_is_reported() {
+        assert_eq!(error_starts("f(1 +, 2 *)"), [5, 10]);
+    }
+
+    #[test]
+    fn missing_comma_is_reported_with_the_rest_of_the_list() {
+        assert_eq!(error_starts("[1 2, @]"), [3, 6]);
+    }
+
+    #[test]
+    fn every_bad_group_is_reported() {
+        assert_eq!(error_starts("(@) + (1 %) * ((%))"), [1, 9, 16]);
+    }
+
+    #[test]
+    fn bad_index_is_reported_with_the_rest_of_the_statement() {
+        assert_eq!(error_starts("a[@] + [%]"), [2, 8]);
+    }
+
+    #[test]
+    fn missing_terminator_has_help() {
+        let errors = parse_program("let x = 5 y").unwrap_err();
+        assert!(matches!(errors[0].kind, ErrorKind::Unexpected('y')));
+        assert!(errors[0].help.is_some());
+    }
+
+    #[test]
+    fn unrecognized_character_has_no_terminator_help() {
+        let errors = parse_program("let x = 5 @").unwrap_err();
+        assert!(matches!(errors[0].kind, ErrorKind::UnrecognizedChar('@')));
+        assert_eq!(errors[0].help, None);
+    }
+
+    #[test]
+    fn lists_still_parse() {
+        for source in ["[]", "[1, 2]", "[1, 2,]", "f()", "f(1, [2], (3))", "a[(1)]"] {
+            assert!(parse_program(source).is_ok(), "{source}");
+        }
+    }
+
+    #[test]
+    fn unclosed_lists_are_still_errors() {
+        for source in ["[1, @", "f(1, @;", "(1 + @]", "a[@"] {
+            assert!(parse_program(source).is_err(), "{source}");
+        }
+    }
+
+    #[test]
+    fn strict_pragma_comes_before_the_first_statement() {
+        assert!(parse_program("// strict\nlet x = 1;").unwrap().strict);
+        assert!(parse_program("// a script\n// strict  \n1").unwrap().strict);
+        assert!(parse_program("// strict").unwrap().strict);
+        assert!(!parse_program("1\n// strict").unwrap().strict);
+        assert!(!parse_program("// strictly\n1").unwrap().strict);
+    }
+
+    #[test]
+    fn errors_in_blocks_are_recovered_from() {
+        assert_eq!(
+            error_starts("let f = fn() { let = 1; 2 @ }\nlet y = %"),
+            [19, 26, 38]
+        );
+        assert_eq!(error_starts("if (x) { @; 1 } else { 2; % }"), [9, 26]);
+    }
+
+    #[test]
+    fn missing_semicolon_is_reported_once() {
+        let errors = parse_program("let x = 1 let y = 2").unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(errors[0].kind, ErrorKind::MissingSemicolon));
+        assert_eq!(errors[0].span, (8..9).into());
+        assert!(errors[0].help.is_some());
+        assert_eq!(error_starts("let a = 1 let b = 2 return a + b"), [8, 18]);
+    }
+
+    #[test]
+    fn unterminated_string_points_at_its_opening_quote() {
+        for source in [r#"let s = "abc"#, r#"let s = "a\""#, r#"let s = "abc\"#] {
+            let errors = parse_program(source).unwrap_err();
+            assert!(
+                matches!(errors[0].kind, ErrorKind::UnterminatedString),
+                "{source}"
+            );
+            assert_eq!(errors[0].span, (8..9).into(), "{source}");
+        }
+    }
+
+    #[test]
+    fn integer_overflow_is_reported() {
+        assert!(parse_program("9223372036854775807").is_ok());
+        let errors = parse_program("1 + 9223372036854775808").unwrap_err();
+        assert!(matches!(errors[0].kind, ErrorKind::IntegerOverflow));
+        assert_eq!(errors[0].span, (4..23).into());
+    }
+
+    #[test]
+    fn end_of_input_errors_point_just_past_the_last_token() {
+        for source in ["let x = 1 +", "[1, 2", "fn(x) {", "let x = 1 +\n\n"] {
+            let errors = parse_program(source).unwrap_err();
+            assert!(
+                matches!(errors[0].kind, ErrorKind::UnexpectedEof),
+                "{source}"
+            );
+            let end = source.trim_end().len();
+            assert_eq!(errors[0].span, (end..end).into(), "{source}");
+        }
+    }
+
+    #[test]
+    fn comments_are_kept_in_order() {
+        let source = "// first\nlet x = \"// not a comment\"; // second\nx";
+        let program = parse_program(source).unwrap();
+        let comments: Vec<_> = program
+            .comments
+            .iter()
+            .map(|comment| (comment.text, &source[comment.span.start..comment.span.end]))
+            .collect();
+        assert_eq!(
+            comments,
+            [("// first", "// first"), ("// second", "// second")]
+        );
+        assert!(program.to_string().contains("// second"));
+    }
+}
@@ -0,0 +1,502 @@
+//! Updating a parsed program after an edit to its source without parsing all of it again.
+//!
+//! Only the top-level statements around the edit are parsed. The rest are carried over from the
+//! old program, with their spans moved to where they ended up in the new source.
+
+use crate::{
+    ast::*,
+    parse::{Error, parse_program},
+};
+
+/// A replacement of part of a source.
+#[derive(Debug, Clone, Copy)]
+pub struct Edit<'e> {
+    /// The replaced part of the old source.
+    pub span: Span,
+    /// What it was replaced with.
+    pub text: &'e str,
+}
+
+impl Edit<'_> {
+    /// Applies the edit to `source`, which should be the source it was made to.
+    pub fn apply(&self, source: &str) -> String {
+        let mut edited = source.to_string();
+        edited.replace_range(self.span.start..self.span.end, self.text);
+        edited
+    }
+
+    /// How far the edit moves text after it.
+    fn offset(&self) -> isize {
+        self.text.len() as isize - (self.span.end - self.span.start) as isize
+    }
+}
+
+/// Parses `source`, which is the source of `program` after `edit`, reusing what it can of
+/// `program`. The result is the same as parsing `source` from scratch.
+pub fn reparse<'a>(
+    program: &Program<'_>,
+    edit: &Edit,
+    source: &'a str,
+) -> Result<Program<'a>, Vec<Error>> {
+    let statements = &program.statements;
+    // The statements either side of the ones the edit touches are reparsed too, since the edit
+    // could join them to or split them from their neighbours.
+    let first = statements
+        .iter()
+        .position(|statement| statement.span().end >= edit.span.start)
+        .unwrap_or(statements.len())
+        .saturating_sub(1);
+    let last = statements
+        .iter()
+        .rposition(|statement| statement.span().start <= edit.span.end)
+        .map_or(0, |i| i + 2)
+        .clamp(first, statements.len());
+
+    // Everything between the unchanged statements, in the new source.
+    let start = match first {
+        0 => 0,
+        first => statements[first].span().start,
+    };
+    let end = match statements.get(last) {
+        Some(statement) => statement.span().start.wrapping_add_signed(edit.offset()),
+        None => source.len(),
+    };
+    let Some(region) = source.get(start..end) else {
+        return parse_program(source);
+    };
+    // Errors are reported as they would be from a full parse, which only that can be sure of.
+    let Ok(reparsed) = parse_program(region) else {
+        return parse_program(source);
+    };
+    if end < source.len() && !is_terminated(region, &reparsed) {
+        return parse_program(source);
+    }
+
+    let unchanged = Rebase::new(source, 0);
+    let inside = Rebase::new(source, start as isize);
+    let after = Rebase::new(source, edit.offset());
+    let old_end = end.wrapping_add_signed(-edit.offset());
+
+    let statements = statements[..first]
+        .iter()
+        .map(|statement| unchanged.statement(statement))
+        .chain(
+            reparsed
+                .statements
+                .iter()
+                .map(|statement| inside.statement(statement)),
+        )
+        .chain(
+            statements[last..]
+                .iter()
+                .map(|statement| after.statement(statement)),
+        )
+        .collect();
+    let comments = program
+        .comments
+        .iter()
+        .filter(|comment| comment.span.end <= start)
+        .map(|comment| unchanged.comment(comment))
+        .chain(
+            reparsed
+                .comments
+                .iter()
+                .map(|comment| inside.comment(comment)),
+        )
+        .chain(
+            program
+                .comments
+                .iter()
+                .filter(|comment| comment.span.start >= old_end)
+                .map(|comment| after.comment(comment)),
+        )
+        .collect();
+    Ok(Program::new(statements, comments))
+}
+
+/// Whether the last statement of `region` ends with a terminator of its own, rather than at the
+/// end of the region where in the full source it would have continued.
+fn is_terminated(region: &str, program: &Program) -> bool {
+    let Some(last) = program.statements.last() else {
+        return true;
+    };
+    let rest = region[last.span().end..].trim_start_matches([' ', '\t']);
+    rest.starts_with([';', '\n']) || rest.starts_with("\r\n") || rest.starts_with("//")
+}
+
+/// Copies `program` without the names and comments it borrows from its source, so that it can
+/// be kept after the source is gone, to pass to [`reparse`] later. Reparsing only looks at where
+/// things are in the old program, not at their text.
+pub fn detach(program: &Program) -> Program<'static> {
+    let rebase = Rebase {
+        source: None,
+        offset: 0,
+    };
+    Program {
+        statements: program
+            .statements
+            .iter()
+            .map(|statement| rebase.statement(statement))
+            .collect(),
+        comments: program
+            .comments
+            .iter()
+            .map(|comment| rebase.comment(comment))
+            .collect(),
+        strict: program.strict,
+    }
+}
+
+/// Borrows the names and comments of a [`detach`]ed program back from `source`, which should be
+/// what it was parsed from.
+pub fn attach<'a>(program: &Program, source: &'a str) -> Program<'a> {
+    let rebase = Rebase::new(source, 0);
+    Program::new(
+        program
+            .statements
+            .iter()
+            .map(|statement| rebase.statement(statement))
+            .collect(),
+        program
+            .comments
+            .iter()
+            .map(|comment| rebase.comment(comment))
+            .collect(),
+    )
+}
+
+/// Moves nodes by `offset` and borrows their text from `source`, or leaves it empty without one.
+struct Rebase<'a> {
+    source: Option<&'a str>,
+    offset: isize,
+}
+
+impl<'a> Rebase<'a> {
+    fn new(source: &'a str, offset: isize) -> Self {
+        Self {
+            source: Some(source),
+            offset,
+        }
+    }
+
+    fn span(&self, span: Span) -> Span {
+        Span {
+            start: span.start.wrapping_add_signed(self.offset),
+            end: span.end.wrapping_add_signed(self.offset),
+        }
+    }
+
+    fn text(&self, span: Span) -> &'a str {
+        self.source
+            .map_or("", |source| &source[span.start..span.end])
+    }
+
+    fn identifier(&self, identifier: &Identifier) -> Identifier<'a> {
+        let span = self.span(identifier.span);
+        Identifier {
+            name: self.text(span),
+            span,
+        }
+    }
+
+    fn comment(&self, comment: &Comment) -> Comment<'a> {
+        let span = self.span(comment.span);
+        Comment {
+            span,
+            text: self.text(span),
+        }
+    }
+
+    fn block(&self, block: &Block) -> Block<'a> {
+        Block {
+            open_span: self.span(block.open_span),
+            statements: block
+                .statements
+                .iter()
+                .map(|statement| self.statement(statement))
+                .collect(),
+            close_span: self.span(block.close_span),
+        }
+    }
+
+    fn statement(&self, statement: &Statement) -> Statement<'a> {
+        match statement {
+            Statement::Let {
+                let_span,
+                name,
+                value,
+            } => Statement::Let {
+                let_span: self.span(*let_span),
+                name: self.identifier(name),
+                value: self.expression(value),
+            },
+            Statement::Return { return_span, value } => Statement::Return {
+                return_span: self.span(*return_span),
+                value: self.expression(value),
+            },
+            Statement::Expression { value, semi } => Statement::Expression {
+                value: self.expression(value),
+                semi: *semi,
+            },
+            Statement::Error(error) => Statement::Error(Error {
+                span: self.span(error.span),
+                ..error.clone()
+            }),
+        }
+    }
+
+    /// Rebases the operands of operators, calls, and indexing from a stack, like
+    /// [`Expression::clone`], so that a deeply nested expression can't overflow the native stack.
+    fn expression(&self, expression: &Expression) -> Expression<'a> {
+        enum Work<'s, 'b> {
+            Visit(&'s Expression<'b>),
+            Build(&'s Expression<'b>),
+        }
+
+        let mut work = vec![Work::Visit(expression)];
+        let mut finished = Vec::new();
+        while let Some(item) = work.pop() {
+            let expression = match item {
+                Work::Visit(expression) => {
+                    work.push(Work::Build(expression));
+                    // Pushed in reverse, so that they're finished in order.
+                    match expression {
+                        Expression::Prefix { right, .. } => work.push(Work::Visit(right)),
+                        Expression::Infix { left, right, .. } => {
+                            work.push(Work::Visit(right));
+                            work.push(Work::Visit(left));
+                        }
+                        Expression::If { condition, .. } => work.push(Work::Visit(condition)),
+                        Expression::Call { function, .. } => work.push(Work::Visit(function)),
+                        Expression::Index {
+                            collection, index, ..
+                        } => {
+                            work.push(Work::Visit(index));
+                            work.push(Work::Visit(collection));
+                        }
+                        _ => {}
+                    }
+                    continue;
+                }
+                Work::Build(expression) => expression,
+            };
+            let mut operand = || Box::new(finished.pop().expect("operands are finished"));
+            let rebased = match expression {
+                Expression::Identifier(identifier) => {
+                    Expression::Identifier(self.identifier(identifier))
+                }
+                Expression::Integer { span, value } => Expression::Integer {
+                    span: self.span(*span),
+                    value: *value,
+                },
+                Expression::Prefix { prefix, .. } => Expression::Prefix {
+                    prefix: Prefix {
+                        span: self.span(prefix.span),
+                        ..prefix.clone()
+                    },
+                    right: operand(),
+                },
+                Expression::Infix { operator, .. } => {
+                    let right = operand();
+                    Expression::Infix {
+                        left: operand(),
+                        operator: *operator,
+                        right,
+                    }
+                }
+                Expression::Boolean { span, value } => Expression::Boolean {
+                    span: self.span(*span),
+                    value: *value,
+                },
+                Expression::If {
+                    if_span,
+                    consequence,
+                    alternative,
+                    ..
+                } => Expression::If {
+                    if_span: self.span(*if_span),
+                    condition: operand(),
+                    consequence: self.block(consequence),
+                    alternative: alternative.as_ref().map(|block| self.block(block)),
+                },
+                Expression::Function {
+                    fn_span,
+                    parameters,
+                    body,
+                } => Expression::Function {
+                    fn_span: self.span(*fn_span),
+                    parameters: parameters
+                        .iter()
+                        .map(|parameter| self.identifier(parameter))
+                        .collect(),
+                    body: self.block(body),
+                },
+                Expression::Call {
+                    arguments,
+                    close_span,
+                    ..
+                } => Expression::Call {
+                    function: operand(),
+                    arguments: arguments
+                        .iter()
+                        .map(|argument| self.expression(argument))
+                        .collect(),
+                    close_span: self.span(*close_span),
+                },
+                Expression::Null(span) => Expression::Null(self.span(*span)),
+                Expression::Error(error) => Expression::Error(Error {
+                    span: self.span(error.span),
+                    ..error.clone()
+                }),
+                Expression::String { span, value } => Expression::String {
+                    span: self.span(*span),
+                    value: value.clone(),
+                },
+                Expression::Array {
+                    open_span,
+                    elements,
+                    close_span,
+                } => Expression::Array {
+                    open_span: self.span(*open_span),
+                    elements: elements
+                        .iter()
+                        .map(|element| self.expression(element))
+                        .collect(),
+                    close_span: self.span(*close_span),
+                },
+                Expression::Index { close_span, .. } => {
+                    let index = operand();
+                    Expression::Index {
+                        collection: operand(),
+                        index,
+                        close_span: self.span(*close_span),
+                    }
+                }
+                Expression::Map {
+                    open_span,
+                    elements,
+                    close_span,
+                } => Expression::Map {
+                    open_span: self.span(*open_span),
+                    elements: elements
+                        .iter()
+                        .map(|(key, value)| (self.expression(key), self.expression(value)))
+                        .collect(),
+                    close_span: self.span(*close_span),
+                },
+            };
+            finished.push(rebased);
+        }
+        finished.pop().expect("the expression was rebased")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Replaces the first `old` in `source` with `new`, and checks that reparsing gives the same
+    /// result as parsing the edited source, both from the program and from it detached.
+    fn check(source: &str, old: &str, new: &str) {
+        let start = source.find(old).expect("`old` is in the source");
+        let edit = Edit {
+            span: (start..start + old.len()).into(),
+            text: new,
+        };
+        let edited = edit.apply(source);
+        let expected = format!("{:?}", parse_program(&edited));
+        let program = parse_program(source).unwrap();
+        assert_eq!(format!("{:?}", reparse(&program, &edit, &edited)), expected);
+        let reparsed = reparse(&detach(&program), &edit, &edited);
+        assert_eq!(format!("{reparsed:?}"), expected);
+    }
+
+    const SOURCE: &str = "let x = 1;\nlet y = 2; // two\nlet z = x + y;\nz\n";
+
+    #[test]
+    fn edit_inside_statement() {
+        check(SOURCE, "2", "20 + 1");
+    }
+
+    #[test]
+    fn edit_across_statements() {
+        check(SOURCE, "1;\nlet y = ", "5 + ");
+    }
+
+    #[test]
+    fn edit_joining_statements() {
+        check(SOURCE, "; // two\nlet z = ", " + ");
+        check(SOURCE, "2; // two\n", "2 ");
+    }
+
+    #[test]
+    fn edit_splitting_statement() {
+        check(SOURCE, " + ", ";\n");
+    }
+
+    #[test]
+    fn edit_at_start() {
+        check(SOURCE, "let x", "let w = 0;\nlet x");
+        check(SOURCE, "let x = 1;\n", "");
+        check(SOURCE, SOURCE, "");
+    }
+
+    #[test]
+    fn edit_at_end() {
+        check(SOURCE, "z\n", "z\nx + y");
+        let edit = Edit {
+            span: (SOURCE.len()..SOURCE.len()).into(),
+            text: "let w = z;",
+        };
+        let edited = edit.apply(SOURCE);
+        let reparsed = reparse(&parse_program(SOURCE).unwrap(), &edit, &edited);
+        assert_eq!(
+            format!("{reparsed:?}"),
+            format!("{:?}", parse_program(&edited))
+        );
+    }
+
+    #[test]
+    fn edit_inside_block() {
+        let source = "let f = fn(a) {\n  let b = a * 2;\n  b\n};\nf(1)\n";
+        check(source, "2", "3");
+        check(source, "b\n}", "b + a\n}");
+        check(source, "let b", "return a;\n  let b");
+    }
+
+    #[test]
+    fn edit_in_comment() {
+        check(SOURCE, "two", "three");
+        check(SOURCE, "// two", "");
+    }
+
+    #[test]
+    fn edit_making_and_fixing_errors() {
+        check(SOURCE, "= 2", "= 2 @");
+        check(SOURCE, "let z = x + y;", "let z = (x + y;");
+    }
+
+    /// Deep expressions are compared by span, since formatting them recurses.
+    #[test]
+    fn deep_expression_is_rebased() {
+        let source = format!("let x = 1;\n{}\n", vec!["1"; 100_000].join(" + "));
+        let edit = Edit {
+            span: (0..0).into(),
+            text: "// sum\n",
+        };
+        let edited = edit.apply(&source);
+        let reparsed = reparse(&parse_program(&source).unwrap(), &edit, &edited).unwrap();
+        let expected = parse_program(&edited).unwrap();
+        let spans = |program: &Program| -> Vec<_> {
+            program.statements.iter().map(Spanned::span).collect()
+        };
+        assert_eq!(spans(&reparsed), spans(&expected));
+    }
+
+    #[test]
+    fn attach_restores_text() {
+        let program = parse_program(SOURCE).unwrap();
+        let attached = attach(&detach(&program), SOURCE);
+        assert_eq!(format!("{attached:?}"), format!("{program:?}"));
+    }
+}
@@ -0,0 +1,72 @@
+//! Printing errors and warnings with the source they point at.
+
+use crate::ast::Span;
+use ariadne::{Config, Report, ReportBuilder, ReportKind};
+use std::{
+    io::{IsTerminal, Write},
+    ops::Range,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+/// Whether reports are colored.
+static COLOR: AtomicBool = AtomicBool::new(true);
+
+#[derive(Clone, Copy, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum, serde::Deserialize))]
+#[cfg_attr(feature = "cli", serde(rename_all = "lowercase"))]
+pub enum ColorChoice {
+    /// Color reports if stderr is a terminal and `NO_COLOR` isn't set
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+/// Sets whether reports are colored from now on.
+pub fn set_color(choice: ColorChoice) {
+    let color = match choice {
+        ColorChoice::Auto => {
+            std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+                && std::io::stderr().is_terminal()
+        }
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+    };
+    COLOR.store(color, Ordering::Relaxed);
+}
+
+/// Whether reports are colored.
+pub fn color() -> bool {
+    COLOR.load(Ordering::Relaxed)
+}
+
+/// Source code along with the name it's reported under, such as its path.
+#[derive(Clone, Copy)]
+pub struct Source<'a> {
+    pub name: &'a str,
+    pub text: &'a str,
+}
+
+impl<'a> Source<'a> {
+    /// Locates `span` within this source for use in a report.
+    pub fn span(&self, span: Span) -> (&'a str, Range<usize>) {
+        (self.name, span.start..span.end)
+    }
+
+    /// Starts a report about `span`, colored according to [`set_color`]. Colors are applied as
+    /// labels are added, so reports have to be configured before anything else.
+    pub fn build_report<'k>(
+        &self,
+        kind: ReportKind<'k>,
+        span: Span,
+    ) -> ReportBuilder<'k, (&'a str, Range<usize>)> {
+        Report::build(kind, self.span(span)).with_config(Config::default().with_color(color()))
+    }
+
+    /// Writes `report` to `out`. Reports are diagnostics, so failing to write one is ignored.
+    pub fn write(&self, report: ReportBuilder<(&str, Range<usize>)>, out: impl Write) {
+        let _ = report
+            .finish()
+            .write((self.name, ariadne::Source::from(self.text)), out);
+    }
+}
@@ -0,0 +1,204 @@
+//! Resolving the names in a program to where they're bound, which both linting and the language
+//! server are built on.
+
+use crate::ast::*;
+use std::collections::HashMap;
+
+/// Callbacks for [`resolve`], made in the order evaluation would come across each name.
+pub(crate) trait Resolve<'p, 'a> {
+    /// What's kept about each binding.
+    type Binding;
+
+    /// Binds `name` to `value`, or to an argument if `value` is `None`. `shadowed` is the binding
+    /// of the same name that the new one hides, if there is one.
+    fn bind(
+        &mut self,
+        name: &'p Identifier<'a>,
+        value: Option<&'p Expression<'a>>,
+        shadowed: Option<&Self::Binding>,
+    ) -> Self::Binding;
+
+    /// A use of `name`, with the binding it refers to. Names that aren't bound anywhere are
+    /// intrinsics or mistakes that evaluation will report.
+    fn reference(&mut self, name: &'p Identifier<'a>, binding: Option<&mut Self::Binding>);
+
+    /// `binding` of `name` can't be referred to anymore, either because its function has ended or
+    /// because a later binding in the same function replaced it.
+    fn unbind(&mut self, _name: &'a str, _binding: Self::Binding) {}
+
+    /// The statements in `span` can't run, because they follow the return at `return_span` in
+    /// the same block.
+    fn unreachable(&mut self, _return_span: Span, _span: Span) {}
+}
+
+/// Resolves every name in `statements`, the top level of a program, scoping names the way
+/// evaluation does.
+pub(crate) fn resolve<'p, 'a, R: Resolve<'p, 'a>>(
+    statements: &'p [Statement<'a>],
+    callbacks: &mut R,
+) {
+    let mut resolver = Resolver {
+        scopes: Vec::new(),
+        callbacks,
+    };
+    resolver.function(&[], statements);
+}
+
+/// A function literal to resolve once the rest of its enclosing function has been, since calls can
+/// happen after names later in the enclosing function are bound.
+type PendingFunction<'p, 'a> = (&'p [Identifier<'a>], &'p [Statement<'a>]);
+
+struct Resolver<'r, 'p, 'a, R: Resolve<'p, 'a>> {
+    /// The bindings of each function being resolved, innermost last. Blocks don't introduce scopes
+    /// of their own.
+    scopes: Vec<HashMap<&'a str, R::Binding>>,
+    callbacks: &'r mut R,
+}
+
+impl<'p, 'a, R: Resolve<'p, 'a>> Resolver<'_, 'p, 'a, R> {
+    fn function(&mut self, parameters: &'p [Identifier<'a>], statements: &'p [Statement<'a>]) {
+        self.scopes.push(HashMap::new());
+        for parameter in parameters {
+            self.bind(parameter, None);
+        }
+
+        let mut pending = Vec::new();
+        self.statements(statements, &mut pending);
+        for (parameters, body) in pending {
+            self.function(parameters, body);
+        }
+
+        let scope = self.scopes.pop().expect("a scope was pushed above");
+        for (name, binding) in scope {
+            self.callbacks.unbind(name, binding);
+        }
+    }
+
+    /// Resolves `statements` and everything in them. Expressions can nest arbitrarily deeply, so
+    /// this walks them with a stack instead of recursing. Everything is pushed in reverse so that
+    /// it's popped in source order.
+    fn statements(
+        &mut self,
+        statements: &'p [Statement<'a>],
+        pending: &mut Vec<PendingFunction<'p, 'a>>,
+    ) {
+        enum Item<'p, 'a> {
+            /// The statement at an index of a list, which is needed to find the statements a
+            /// return makes unreachable.
+            Statement(&'p [Statement<'a>], usize),
+            Expression(&'p Expression<'a>),
+            /// A `let` binding, made once its value has been resolved.
+            Bind(&'p Identifier<'a>, &'p Expression<'a>),
+        }
+        fn push_statements<'p, 'a>(stack: &mut Vec<Item<'p, 'a>>, statements: &'p [Statement<'a>]) {
+            stack.extend(
+                (0..statements.len())
+                    .rev()
+                    .map(|i| Item::Statement(statements, i)),
+            );
+        }
+
+        let mut stack = Vec::new();
+        push_statements(&mut stack, statements);
+        while let Some(item) = stack.pop() {
+            let expression = match item {
+                Item::Statement(statements, i) => match &statements[i] {
+                    Statement::Let { name, value, .. } => {
+                        stack.push(Item::Bind(name, value));
+                        value
+                    }
+                    Statement::Return { return_span, value } => {
+                        if let (Some(first), Some(last)) =
+                            (statements.get(i + 1), statements.last())
+                        {
+                            self.callbacks
+                                .unreachable(*return_span, first.span().join(last.span()));
+                        }
+                        value
+                    }
+                    Statement::Expression { value, .. } => value,
+                    Statement::Error(_) => continue,
+                },
+                Item::Expression(expression) => expression,
+                Item::Bind(name, value) => {
+                    self.bind(name, Some(value));
+                    continue;
+                }
+            };
+            match expression {
+                Expression::Identifier(ident) => {
+                    let binding = self
+                        .scopes
+                        .iter_mut()
+                        .rev()
+                        .find_map(|scope| scope.get_mut(ident.name));
+                    self.callbacks.reference(ident, binding);
+                }
+                Expression::Prefix { right, .. } => stack.push(Item::Expression(right)),
+                Expression::Infix { left, right, .. } => {
+                    stack.push(Item::Expression(right));
+                    stack.push(Item::Expression(left));
+                }
+                Expression::If {
+                    condition,
+                    consequence,
+                    alternative,
+                    ..
+                } => {
+                    if let Some(alternative) = alternative {
+                        push_statements(&mut stack, &alternative.statements);
+                    }
+                    push_statements(&mut stack, &consequence.statements);
+                    stack.push(Item::Expression(condition));
+                }
+                Expression::Function {
+                    parameters, body, ..
+                } => pending.push((parameters, &body.statements)),
+                Expression::Call {
+                    function,
+                    arguments,
+                    ..
+                } => {
+                    stack.extend(arguments.iter().rev().map(Item::Expression));
+                    stack.push(Item::Expression(function));
+                }
+                Expression::Array { elements, .. } => {
+                    stack.extend(elements.iter().rev().map(Item::Expression));
+                }
+                Expression::Index {
+                    collection, index, ..
+                } => {
+                    stack.push(Item::Expression(index));
+                    stack.push(Item::Expression(collection));
+                }
+                Expression::Map { elements, .. } => {
+                    for (key, value) in elements.iter().rev() {
+                        stack.push(Item::Expression(value));
+                        stack.push(Item::Expression(key));
+                    }
+                }
+                Expression::Integer { .. }
+                | Expression::Boolean { .. }
+                | Expression::Null(_)
+                | Expression::String { .. }
+                | Expression::Error(_) => {}
+            }
+        }
+    }
+
+    fn bind(&mut self, name: &'p Identifier<'a>, value: Option<&'p Expression<'a>>) {
+        let shadowed = self
+            .scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name.name));
+        let binding = self.callbacks.bind(name, value, shadowed);
+        let scope = self
+            .scopes
+            .last_mut()
+            .expect("bindings happen inside a scope");
+        if let Some(replaced) = scope.insert(name.name, binding) {
+            self.callbacks.unbind(name.name, replaced);
+        }
+    }
+}
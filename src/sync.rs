@@ -0,0 +1,80 @@
+//! The shared-ownership types behind values and scopes. With the `sync` feature these are
+//! thread-safe, so an [`Environment`](crate::eval::Environment) and its values can be sent to
+//! other threads.
+
+#[cfg(not(feature = "sync"))]
+pub use std::{
+    cell::RefCell,
+    rc::{Rc, Weak},
+};
+
+#[cfg(feature = "sync")]
+pub use std::sync::{Arc as Rc, Weak};
+
+#[cfg(feature = "sync")]
+use std::sync::TryLockError;
+
+/// `Send + Sync` with the `sync` feature, and implemented for everything otherwise, so that trait
+/// objects behind an [`Rc`] can be shared between threads exactly when values can.
+#[cfg(not(feature = "sync"))]
+pub trait MaybeSync {}
+
+#[cfg(not(feature = "sync"))]
+impl<T: ?Sized> MaybeSync for T {}
+
+#[cfg(feature = "sync")]
+pub trait MaybeSync: Send + Sync {}
+
+#[cfg(feature = "sync")]
+impl<T: Send + Sync + ?Sized> MaybeSync for T {}
+
+/// A [`RwLock`](std::sync::RwLock) with the interface of [`std::cell::RefCell`]. Like a
+/// `RefCell`, it panics when borrowed in a way that conflicts with an existing borrow, rather than
+/// blocking, which would deadlock when the existing borrow is on the same thread.
+#[cfg(feature = "sync")]
+#[derive(Default)]
+pub struct RefCell<T: ?Sized>(std::sync::RwLock<T>);
+
+#[cfg(feature = "sync")]
+impl<T> RefCell<T> {
+    pub fn new(value: T) -> Self {
+        Self(std::sync::RwLock::new(value))
+    }
+}
+
+#[cfg(feature = "sync")]
+impl<T: ?Sized> RefCell<T> {
+    pub fn borrow(&self) -> std::sync::RwLockReadGuard<'_, T> {
+        self.0.try_read().unwrap_or_else(|err| match err {
+            TryLockError::WouldBlock => panic!("already mutably borrowed"),
+            TryLockError::Poisoned(err) => panic!("{err}"),
+        })
+    }
+
+    pub fn borrow_mut(&self) -> std::sync::RwLockWriteGuard<'_, T> {
+        self.0.try_write().unwrap_or_else(|err| match err {
+            TryLockError::WouldBlock => panic!("already borrowed"),
+            TryLockError::Poisoned(err) => panic!("{err}"),
+        })
+    }
+}
+
+#[cfg(feature = "sync")]
+const _: () = {
+    fn assert_send<T: Send>() {}
+    let _ = assert_send::<crate::eval::Environment<'static>>;
+};
+
+#[cfg(all(test, feature = "sync"))]
+mod tests {
+    use super::RefCell;
+
+    #[test]
+    #[should_panic(expected = "already borrowed")]
+    fn nested_mutable_borrows_panic_instead_of_deadlocking() {
+        let cell = RefCell::new(0);
+        let _first = cell.borrow();
+        let _second = cell.borrow();
+        *cell.borrow_mut() += 1;
+    }
+}
@@ -1,5 +1,15 @@
-use crate::ast::{Block, Identifier};
-use std::{collections::HashMap, fmt::Display, hash::Hash, rc::Rc};
+//! The values programs work with.
+
+use crate::{
+    ast::{Block, Identifier, Quoted, Span},
+    eval::Scope,
+    sync::{Rc, RefCell},
+};
+use std::{
+    collections::HashMap,
+    fmt::{Debug, Display},
+    hash::{DefaultHasher, Hash, Hasher},
+};
 use strum::{Display, EnumDiscriminants};
 
 #[derive(Clone, EnumDiscriminants)]
@@ -7,39 +17,119 @@ use strum::{Display, EnumDiscriminants};
 pub enum Value<'a> {
     Int(i64),
     Bool(bool),
-    String(String),
-    Array(Vec<Self>),
-    Map(HashMap<Self, Self>),
+    String(Rc<String>),
+    Bytes(Rc<Vec<u8>>),
+    Array(Rc<Vec<Self>>),
+    Map(Rc<HashMap<Self, Self>>),
     Null,
     Function(Rc<Function<'a>>),
+    Error(Rc<ErrorValue>),
 }
 
+/// Collections nested deeper than this are elided when formatting, so that printing a deeply
+/// nested value can't overflow the stack. Values can't contain themselves, so there's no need to
+/// detect cycles.
+const MAX_FORMAT_DEPTH: usize = 64;
+
+/// Alternate formatting (`{:#}` and `{:#?}`) spreads collections over multiple lines.
 impl Display for Value<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::Int(i) => i.fmt(f),
-            Self::Bool(b) => b.fmt(f),
-            Self::String(s) => s.fmt(f),
-            Self::Array(a) => f.debug_list().entries(a.iter().map(DebugDisplay)).finish(),
-            Self::Map(m) => f
-                .debug_map()
-                .entries(m.iter().map(|(k, v)| (DebugDisplay(k), DebugDisplay(v))))
-                .finish(),
+            Self::Int(i) => Display::fmt(i, f),
+            Self::Bool(b) => Display::fmt(b, f),
+            Self::String(s) => Display::fmt(s, f),
+            Self::Bytes(b) => {
+                write!(f, "b\"")?;
+                for byte in b.iter() {
+                    write!(f, "{}", std::ascii::escape_default(*byte))?;
+                }
+                write!(f, "\"")
+            }
+            Self::Array(_) | Self::Map(_) => Nested {
+                value: self,
+                depth: 0,
+                inspect: false,
+            }
+            .fmt(f),
             Self::Null => write!(f, "null"),
             Self::Function(_) => write!(f, "<function>"),
+            Self::Error(error) => write!(f, "error: {}", error.message),
         }
     }
 }
 
-struct DebugDisplay<'a, T: Display>(&'a T);
+/// Formats values the way they'd be written in source, so that strings are quoted and escaped.
+impl Debug for Value<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Nested {
+            value: self,
+            depth: 0,
+            inspect: true,
+        }
+        .fmt(f)
+    }
+}
 
-impl<T: Display> std::fmt::Debug for DebugDisplay<'_, T> {
+/// A value inside `depth` collections, formatted with either `Display` or `Debug`.
+struct Nested<'v, 'a> {
+    value: &'v Value<'a>,
+    depth: usize,
+    inspect: bool,
+}
+
+impl<'v, 'a> Nested<'v, 'a> {
+    fn child(&self, value: &'v Value<'a>) -> Self {
+        Self {
+            value,
+            depth: self.depth + 1,
+            inspect: self.inspect,
+        }
+    }
+}
+
+impl Debug for Nested<'_, '_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        self.0.fmt(f)
+        match self.value {
+            Value::Array(_) if self.depth >= MAX_FORMAT_DEPTH => write!(f, "[...]"),
+            Value::Map(_) if self.depth >= MAX_FORMAT_DEPTH => write!(f, "{{...}}"),
+            Value::Array(a) => f
+                .debug_list()
+                .entries(a.iter().map(|v| self.child(v)))
+                .finish(),
+            Value::Map(m) => f
+                .debug_map()
+                .entries(m.iter().map(|(k, v)| (self.child(k), self.child(v))))
+                .finish(),
+            Value::String(s) if self.inspect => write!(f, "{}", Quoted(s)),
+            value => Display::fmt(value, f),
+        }
     }
 }
 
 impl Value<'_> {
+    /// Copies this value such that no part of the copy is shared with the original.
+    pub fn deep_clone(&self) -> Self {
+        match self {
+            Self::Bytes(b) => Self::Bytes(Rc::new(b.to_vec())),
+            Self::Array(a) => Self::Array(Rc::new(a.iter().map(Self::deep_clone).collect())),
+            Self::Map(m) => Self::Map(Rc::new(
+                m.iter()
+                    .map(|(k, v)| (k.deep_clone(), v.deep_clone()))
+                    .collect(),
+            )),
+            value => value.clone(),
+        }
+    }
+
+    /// Whether programs can use this value as a map key.
+    pub fn is_hashable(&self) -> bool {
+        match self {
+            Self::Int(_) | Self::Bool(_) | Self::String(_) | Self::Bytes(_) => true,
+            Self::Array(a) => a.iter().all(Self::is_hashable),
+            Self::Map(_) | Self::Null | Self::Function(_) | Self::Error(_) => false,
+        }
+    }
+
     pub fn truthy(&self) -> bool {
         match self {
             Self::Int(1..)
@@ -48,26 +138,56 @@ impl Value<'_> {
             | Self::Map(_)
             | Self::Function(_) => true,
             Self::String(s) => !s.is_empty(),
+            Self::Bytes(b) => !b.is_empty(),
             _ => false,
         }
     }
 }
 
+/// An error as a value, which propagates out of any expression that operates on it.
+#[derive(Clone, Debug)]
+pub struct ErrorValue {
+    pub message: String,
+    /// Where the error was created.
+    pub span: Span,
+    /// Spans of the calls the error propagated through, innermost first.
+    pub trace: Vec<Span>,
+}
+
 pub struct Function<'a> {
     pub name: Option<Identifier<'a>>,
     pub parameters: Vec<Identifier<'a>>,
     pub body: Block<'a>,
+    /// The scope the function was defined in. It usually holds the function too, so this is a
+    /// reference cycle, which [`Scopes`](crate::eval::Scopes) breaks once evaluation is over.
+    pub scope: Rc<RefCell<Scope<'a>>>,
 }
 
+/// Hashes values by what [`PartialEq`] compares, so any value can be hashed, not just those that
+/// programs can use as map keys.
 impl Hash for Value<'_> {
-    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
         match self {
             Self::Int(i) => i.hash(state),
             Self::Bool(b) => b.hash(state),
             Self::String(s) => s.hash(state),
-            Self::Array(_) | Self::Map(_) | Self::Function(_) | Self::Null => {
-                panic!("map key must be int, bool, or string")
+            Self::Bytes(b) => b.hash(state),
+            Self::Array(a) => a.hash(state),
+            // Maps are equal regardless of the order of their entries, so their hash can't depend
+            // on it either.
+            Self::Map(m) => {
+                let entries = m.iter().fold(0u64, |sum, entry| {
+                    let mut hasher = DefaultHasher::new();
+                    entry.hash(&mut hasher);
+                    sum.wrapping_add(hasher.finish())
+                });
+                m.len().hash(state);
+                entries.hash(state);
             }
+            Self::Null => {}
+            Self::Function(function) => Rc::as_ptr(function).hash(state),
+            Self::Error(error) => error.message.hash(state),
         }
     }
 }
@@ -78,12 +198,105 @@ impl PartialEq for Value<'_> {
             (Self::Int(l), Self::Int(r)) => l == r,
             (Self::Bool(l), Self::Bool(r)) => l == r,
             (Self::String(l), Self::String(r)) => l == r,
+            (Self::Bytes(l), Self::Bytes(r)) => l == r,
             (Self::Array(l), Self::Array(r)) => l == r,
             (Self::Map(l), Self::Map(r)) => l == r,
             (Self::Null, Self::Null) => true,
+            (Self::Error(l), Self::Error(r)) => l.message == r.message,
+            (Self::Function(l), Self::Function(r)) => Rc::ptr_eq(l, r),
             _ => false,
         }
     }
 }
 
 impl Eq for Value<'_> {}
+
+#[cfg(test)]
+// Functions are hashed by identity, so the scopes they hold can change without changing the hash.
+#[allow(clippy::mutable_key_type)]
+mod tests {
+    use super::*;
+    use std::hash::BuildHasher;
+
+    fn hash(value: &Value) -> u64 {
+        std::hash::RandomState::new().hash_one(value)
+    }
+
+    #[test]
+    fn arrays_are_map_keys() {
+        let key = Value::from(vec![1, 2]);
+        let map = HashMap::from([(key, Value::from("a"))]);
+        assert_eq!(map.get(&Value::from(vec![1, 2])), Some(&Value::from("a")));
+        assert_eq!(map.get(&Value::from(vec![2, 1])), None);
+    }
+
+    #[test]
+    fn equal_maps_hash_equally() {
+        let state = std::hash::RandomState::new();
+        let forward: HashMap<_, _> = (0..100).map(|i| (Value::Int(i), Value::Int(i))).collect();
+        let backward: HashMap<_, _> = (0..100)
+            .rev()
+            .map(|i| (Value::Int(i), Value::Int(i)))
+            .collect();
+        assert_eq!(
+            state.hash_one(Value::Map(Rc::new(forward))),
+            state.hash_one(Value::Map(Rc::new(backward)))
+        );
+    }
+
+    #[test]
+    fn every_value_hashes() {
+        let map = Value::Map(Rc::new(HashMap::from([(
+            Value::from(vec![Value::Null]),
+            Value::Null,
+        )])));
+        let error = Value::Error(Rc::new(ErrorValue {
+            message: "oops".to_string(),
+            span: Span::default(),
+            trace: Vec::new(),
+        }));
+        for value in [Value::Null, map, error] {
+            hash(&value);
+        }
+    }
+
+    #[test]
+    fn inspected_strings_parse_back() {
+        let strings = [
+            "plain",
+            "quote \" and backslash \\",
+            "line\nreturn\rtab\t",
+            "nul\0 escape\u{1b}[0m delete\u{7f} next line\u{85}",
+            "accent é, combining e\u{301}, emoji 🐒, separator\u{2028}",
+        ];
+        for string in strings {
+            let value = || Value::from(vec![Value::from(string)]);
+            let source = format!("{:?}", value());
+            let program = crate::parse::parse_program(&source).unwrap();
+            let parsed = crate::eval::Environment::default().eval(program).unwrap();
+            assert_eq!(parsed, value(), "{source}");
+        }
+    }
+
+    #[test]
+    fn alternate_formatting_spreads_collections() {
+        let value = Value::from(vec![Value::from(vec![1, 2]), Value::from("a")]);
+        assert_eq!(format!("{value}"), "[[1, 2], a]");
+        assert_eq!(format!("{value:?}"), r#"[[1, 2], "a"]"#);
+        assert_eq!(
+            format!("{value:#?}"),
+            "[\n    [\n        1,\n        2,\n    ],\n    \"a\",\n]"
+        );
+    }
+
+    #[test]
+    fn deeply_nested_collections_are_elided() {
+        let mut value = Value::Int(1);
+        for _ in 0..MAX_FORMAT_DEPTH + 1 {
+            value = Value::from(vec![value]);
+        }
+        let formatted = value.to_string();
+        assert!(formatted.contains("[...]"));
+        assert!(!formatted.contains('1'));
+    }
+}
@@ -0,0 +1,92 @@
+//! The interpreter's interface to JavaScript, for running scripts in a browser.
+
+use crate::{
+    eval::{Environment, Error, ErrorKind, Output},
+    lint,
+    parse::parse_program,
+    report::{self, ColorChoice, Source},
+    sync::{Rc, RefCell},
+    value::Value,
+};
+use wasm_bindgen::prelude::*;
+
+/// Runs `source`, returning everything it printed followed by its result or any errors.
+#[wasm_bindgen]
+pub fn run(source: &str) -> String {
+    let output = Rc::new(RefCell::new(Vec::new()));
+    execute(source, output.clone());
+    let output = output.borrow();
+    String::from_utf8_lossy(&output).into_owned()
+}
+
+/// Like [`run`], but passes output to `on_output` as it's written rather than returning it all
+/// at the end. JavaScript functions can't be sent between threads, so this isn't available with
+/// the `sync` feature.
+#[cfg(not(feature = "sync"))]
+#[wasm_bindgen(js_name = runStreaming)]
+pub fn run_streaming(source: &str, on_output: js_sys::Function) {
+    execute(source, Rc::new(RefCell::new(Callback(on_output))));
+}
+
+/// Passes everything written to it to a JavaScript function as a string.
+#[cfg(not(feature = "sync"))]
+struct Callback(js_sys::Function);
+
+#[cfg(not(feature = "sync"))]
+impl std::io::Write for Callback {
+    /// Output is always written a formatted piece at a time, so `buf` never ends partway through
+    /// a character.
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let text = JsValue::from_str(&String::from_utf8_lossy(buf));
+        self.0
+            .call1(&JsValue::NULL, &text)
+            .map_err(|_| std::io::Error::other("output callback threw an exception"))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Runs `text`, writing what it prints and then its result or any errors to `output`.
+fn execute(text: &str, output: Rc<RefCell<dyn Output>>) {
+    report::set_color(ColorChoice::Never);
+    let source = Source {
+        name: "<playground>",
+        text,
+    };
+    let program = match parse_program(text) {
+        Ok(program) => program,
+        Err(errors) => {
+            for error in errors {
+                error.write_report(source, &mut *output.borrow_mut());
+            }
+            return;
+        }
+    };
+    for warning in lint::check(&program) {
+        warning.write_report(source, program.strict, &mut *output.borrow_mut());
+    }
+
+    let mut env = Environment {
+        output: Some(output.clone()),
+        ..Default::default()
+    };
+    let result = env.eval(program);
+    let mut out = output.borrow_mut();
+    match result {
+        Err(Error {
+            kind: ErrorKind::Exit(_),
+            ..
+        })
+        | Ok(Value::Null) => {}
+        Err(e) => e.write_report(source, &mut *out),
+        Ok(Value::Error(error)) => {
+            Error::from(Rc::unwrap_or_clone(error)).write_report(source, &mut *out)
+        }
+        Ok(value) => {
+            let _ = writeln!(out, "{value}");
+        }
+    }
+}
@@ -0,0 +1,13 @@
+//! Checks that every program in `examples/` prints what its `.out` file says it does.
+
+use std::process::Command;
+
+#[test]
+fn examples() {
+    let status = Command::new(env!("CARGO_BIN_EXE_monkey"))
+        .args(["test", "--examples"])
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .status()
+        .expect("couldn't run monkey");
+    assert!(status.success(), "some examples failed");
+}